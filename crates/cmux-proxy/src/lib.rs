@@ -1,28 +1,32 @@
 use std::{
     cmp::min,
+    collections::{HashMap, VecDeque},
     convert::Infallible,
     future::Future,
     io,
-    net::{SocketAddr, TcpListener as StdTcpListener},
+    net::{IpAddr, SocketAddr, TcpListener as StdTcpListener},
     pin::Pin,
     str::FromStr,
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use bytes::Bytes;
 use futures_util::future;
-use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
+use http::{
+    HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode, Uri, Version,
+};
 use http_body_util::{BodyExt, Empty, Full};
 use hyper::body::Incoming;
 use hyper::server::conn::{http1, http2};
 use hyper::service::service_fn;
 use hyper_util::client::legacy::{connect::HttpConnector, Client};
 use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
-use std::sync::Arc;
-use tokio::io::{copy_bidirectional, AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::{copy_bidirectional, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Notify;
+use tokio::sync::{oneshot, watch};
 use tokio::task::{JoinHandle, JoinSet};
 use tracing::{error, info, warn};
 
@@ -32,9 +36,28 @@ type BoxBody =
     http_body_util::combinators::BoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// Signature opening a PROXY protocol v2 header.
+const PROXY_V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest legal PROXY protocol v1 header, CRLF included.
+const PROXY_V1_MAX_LEN: usize = 107;
+/// How long a client may take to send its PROXY protocol header before it is dropped.
+const PROXY_HEADER_TIMEOUT_SECS: u64 = 5;
 const HOST_OVERRIDE_HEADER: &str = "X-Cmux-Host-Override";
+const TIMEOUT_HEADER: &str = "x-cmux-timeout-ms";
+/// Deadline forwarded to upstreams, in milliseconds, whenever a request timeout applies.
+const FORWARDED_TIMEOUT_HEADER: &str = "x-request-timeout-ms";
 const HTTP2_KEEP_ALIVE_INTERVAL_SECS: u64 = 30;
 const HTTP2_KEEP_ALIVE_TIMEOUT_SECS: u64 = 10;
+/// Default [`ProxyConfig::trusted_headers`]: only the Host override, since routing needs the
+/// port and workspace headers from every client unless an edge sets them.
+pub const DEFAULT_TRUSTED_HEADERS: &[&str] = &[HOST_OVERRIDE_HEADER];
+/// Default [`ProxyConfig::health_path`], chosen to be unlikely to collide with proxied apps.
+pub const DEFAULT_HEALTH_PATH: &str = "/__cmux_health";
+/// Default [`ProxyConfig::max_request_header_bytes`].
+pub const DEFAULT_MAX_REQUEST_HEADER_BYTES: usize = 64 * 1024;
+/// Smallest read buffer hyper's HTTP/1 server accepts.
+const HTTP1_MIN_BUF_SIZE: usize = 8192;
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
 
 trait ClientKeepAliveConfig {
     fn set_pool_max_idle_per_host(&mut self, max: usize);
@@ -65,6 +88,7 @@ trait Http1ServerConfig {
     fn set_keep_alive(&mut self, val: bool);
     fn set_preserve_header_case(&mut self, val: bool);
     fn set_title_case_headers(&mut self, val: bool);
+    fn set_max_buf_size(&mut self, max: usize);
 }
 
 impl Http1ServerConfig for http1::Builder {
@@ -79,11 +103,16 @@ impl Http1ServerConfig for http1::Builder {
     fn set_title_case_headers(&mut self, val: bool) {
         self.title_case_headers(val);
     }
+
+    fn set_max_buf_size(&mut self, max: usize) {
+        self.max_buf_size(max);
+    }
 }
 
 trait Http2ServerConfig {
     fn set_keep_alive_interval(&mut self, interval: Option<Duration>);
     fn set_keep_alive_timeout(&mut self, timeout: Duration);
+    fn set_max_header_list_size(&mut self, max: u32);
 }
 
 impl<E> Http2ServerConfig for http2::Builder<E> {
@@ -94,6 +123,10 @@ impl<E> Http2ServerConfig for http2::Builder<E> {
     fn set_keep_alive_timeout(&mut self, timeout: Duration) {
         self.keep_alive_timeout(timeout);
     }
+
+    fn set_max_header_list_size(&mut self, max: u32) {
+        self.max_header_list_size(max);
+    }
 }
 
 fn configure_http_client_builder(builder: &mut impl ClientKeepAliveConfig) {
@@ -104,21 +137,61 @@ fn configure_http_client_builder(builder: &mut impl ClientKeepAliveConfig) {
     builder.set_http2_keep_alive_while_idle(true);
 }
 
-fn configure_http1_server_builder(builder: &mut impl Http1ServerConfig) {
-    builder.set_keep_alive(true);
-    builder.set_preserve_header_case(true);
-    builder.set_title_case_headers(true);
+fn configure_http1_server_builder(
+    builder: &mut impl Http1ServerConfig,
+    opts: &Http1Options,
+    max_header_bytes: usize,
+) {
+    builder.set_keep_alive(opts.keep_alive);
+    builder.set_preserve_header_case(opts.preserve_header_case);
+    builder.set_title_case_headers(opts.title_case_headers);
+    // hyper answers a request head that overflows the read buffer with 431
+    builder.set_max_buf_size(max_header_bytes.max(HTTP1_MIN_BUF_SIZE));
 }
 
-fn configure_http2_server_builder(builder: &mut impl Http2ServerConfig) {
+fn configure_http2_server_builder(builder: &mut impl Http2ServerConfig, max_header_bytes: usize) {
     builder.set_keep_alive_interval(Some(Duration::from_secs(HTTP2_KEEP_ALIVE_INTERVAL_SECS)));
     builder.set_keep_alive_timeout(Duration::from_secs(HTTP2_KEEP_ALIVE_TIMEOUT_SECS));
+    builder.set_max_header_list_size(u32::try_from(max_header_bytes).unwrap_or(u32::MAX));
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn reads_proxy_protocol_headers() {
+        let mut v1: &[u8] = b"PROXY TCP4 203.0.113.7 10.0.0.1 51234 8080\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(
+            read_proxy_protocol_header(&mut v1).await.unwrap(),
+            Some(SocketAddr::from(([203, 0, 113, 7], 51234)))
+        );
+        // The request itself is left unread
+        assert_eq!(v1, b"GET / HTTP/1.1\r\n");
+
+        let mut v2 = PROXY_V2_SIGNATURE.to_vec();
+        v2.extend_from_slice(&[
+            0x21, 0x11, 0, 12, 198, 51, 100, 2, 10, 0, 0, 1, 0x1f, 0x90, 0, 80,
+        ]);
+        assert_eq!(
+            read_proxy_protocol_header(&mut v2.as_slice())
+                .await
+                .unwrap(),
+            Some(SocketAddr::from(([198, 51, 100, 2], 8080)))
+        );
+
+        for malformed in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 203.0.113.7 10.0.0.1 51234\r\n",
+            b"PROXY TCP6 203.0.113.7 10.0.0.1 51234 8080\r\n",
+        ] {
+            let err = read_proxy_protocol_header(&mut &malformed[..])
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{malformed:?}");
+        }
+    }
+
     #[derive(Default)]
     struct RecordingClientBuilder {
         pool_max_idle: Option<usize>,
@@ -150,6 +223,7 @@ mod tests {
         keep_alive: Option<bool>,
         preserve_header_case: Option<bool>,
         title_case_headers: Option<bool>,
+        max_buf_size: Option<usize>,
     }
 
     impl Http1ServerConfig for RecordingHttp1Builder {
@@ -164,12 +238,17 @@ mod tests {
         fn set_title_case_headers(&mut self, val: bool) {
             self.title_case_headers = Some(val);
         }
+
+        fn set_max_buf_size(&mut self, max: usize) {
+            self.max_buf_size = Some(max);
+        }
     }
 
     #[derive(Default)]
     struct RecordingHttp2Builder {
         interval: Option<Option<Duration>>,
         timeout: Option<Duration>,
+        max_header_list_size: Option<u32>,
     }
 
     impl Http2ServerConfig for RecordingHttp2Builder {
@@ -180,6 +259,10 @@ mod tests {
         fn set_keep_alive_timeout(&mut self, timeout: Duration) {
             self.timeout = Some(timeout);
         }
+
+        fn set_max_header_list_size(&mut self, max: u32) {
+            self.max_header_list_size = Some(max);
+        }
     }
 
     #[test]
@@ -201,16 +284,37 @@ mod tests {
     #[test]
     fn configures_http1_server_builder_with_keep_alive_and_headers() {
         let mut builder = RecordingHttp1Builder::default();
-        configure_http1_server_builder(&mut builder);
+        configure_http1_server_builder(
+            &mut builder,
+            &Http1Options::default(),
+            DEFAULT_MAX_REQUEST_HEADER_BYTES,
+        );
         assert_eq!(builder.keep_alive, Some(true));
         assert_eq!(builder.preserve_header_case, Some(true));
         assert_eq!(builder.title_case_headers, Some(true));
+        assert_eq!(builder.max_buf_size, Some(DEFAULT_MAX_REQUEST_HEADER_BYTES));
+    }
+
+    #[test]
+    fn configures_http1_server_builder_from_options() {
+        let mut builder = RecordingHttp1Builder::default();
+        let opts = Http1Options {
+            keep_alive: false,
+            preserve_header_case: true,
+            title_case_headers: false,
+        };
+        configure_http1_server_builder(&mut builder, &opts, 1024);
+        assert_eq!(builder.keep_alive, Some(false));
+        assert_eq!(builder.preserve_header_case, Some(true));
+        assert_eq!(builder.title_case_headers, Some(false));
+        // Clamped to hyper's minimum buffer size
+        assert_eq!(builder.max_buf_size, Some(HTTP1_MIN_BUF_SIZE));
     }
 
     #[test]
     fn configures_http2_server_builder_keep_alive() {
         let mut builder = RecordingHttp2Builder::default();
-        configure_http2_server_builder(&mut builder);
+        configure_http2_server_builder(&mut builder, DEFAULT_MAX_REQUEST_HEADER_BYTES);
         assert_eq!(
             builder.interval,
             Some(Some(Duration::from_secs(HTTP2_KEEP_ALIVE_INTERVAL_SECS)))
@@ -219,6 +323,61 @@ mod tests {
             builder.timeout,
             Some(Duration::from_secs(HTTP2_KEEP_ALIVE_TIMEOUT_SECS))
         );
+        assert_eq!(
+            builder.max_header_list_size,
+            Some(DEFAULT_MAX_REQUEST_HEADER_BYTES as u32)
+        );
+    }
+
+    #[test]
+    fn circuit_breaker_lets_one_probe_through_when_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(30), Duration::from_millis(50));
+        breaker.record_failure("up:1");
+        assert!(breaker.is_open("up:1"));
+        assert!(breaker.admit("up:1").is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        // Checking does not change state; admitting claims the single probe
+        assert!(!breaker.is_open("up:1"));
+        assert!(!breaker.is_open("up:1"));
+        assert!(breaker.admit("up:1").is_none());
+        assert!(breaker.is_open("up:1"));
+        assert!(breaker.admit("up:1").is_some());
+
+        // A failed probe re-opens for a full cooldown
+        breaker.record_failure("up:1");
+        assert!(breaker.admit("up:1").is_some());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.admit("up:1").is_none());
+
+        // A successful probe closes the breaker
+        breaker.record_success("up:1");
+        assert!(breaker.admit("up:1").is_none());
+        assert!(breaker.admit("up:1").is_none());
+
+        // A probe that never reports back is replaced after a cooldown
+        breaker.record_failure("up:1");
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.admit("up:1").is_none());
+        assert!(breaker.admit("up:1").is_some());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.admit("up:1").is_none());
+    }
+
+    #[test]
+    fn byte_counters_are_capped() {
+        let counters = ByteCounters::default();
+        for i in 0..ByteCounters::MAX_WORKSPACES + 100 {
+            counters
+                .counter(&format!("ws{i}"))
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        let snapshot = counters.snapshot();
+        assert_eq!(snapshot.len(), ByteCounters::MAX_WORKSPACES + 1);
+        assert_eq!(snapshot[ByteCounters::OTHER_WORKSPACE], 100);
+        // Workspaces tracked before the cap keep their own counter
+        counters.counter("ws0").fetch_add(1, Ordering::Relaxed);
+        assert_eq!(counters.get("ws0"), 2);
     }
 }
 
@@ -277,6 +436,60 @@ impl AsyncWrite for BufferedStream {
     }
 }
 
+/// Tunnel endpoint that fails reads once more than `limit` bytes have come from it, so a
+/// tunnel copy aborts when one direction exceeds its byte budget.
+struct CappedIo<T> {
+    inner: T,
+    read: u64,
+    limit: u64,
+}
+
+impl<T> CappedIo<T> {
+    fn new(inner: T, limit: Option<u64>) -> Self {
+        Self {
+            inner,
+            read: 0,
+            limit: limit.unwrap_or(u64::MAX),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CappedIo<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.read += (buf.filled().len() - before) as u64;
+            if self.read > self.limit {
+                return Poll::Ready(Err(io::Error::other("tunnel byte limit exceeded")));
+            }
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CappedIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, data)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
 fn empty_body() -> BoxBody {
     Empty::<Bytes>::new()
         .map_err(|never: Infallible| match never {})
@@ -293,11 +506,574 @@ fn incoming_to_box(b: Incoming) -> BoxBody {
     b.map_err(|e| -> BoxError { Box::new(e) }).boxed()
 }
 
+/// Shared per-workspace tallies of response body bytes delivered to clients.
+/// Requests without a workspace are counted under [`ByteCounters::DEFAULT_WORKSPACE`].
+/// Workspace names come from request headers, so at most [`ByteCounters::MAX_WORKSPACES`]
+/// are tracked; bytes for any further workspace go to [`ByteCounters::OTHER_WORKSPACE`].
+#[derive(Clone, Debug, Default)]
+pub struct ByteCounters {
+    inner: Arc<Mutex<HashMap<String, Arc<AtomicU64>>>>,
+}
+
+impl ByteCounters {
+    pub const DEFAULT_WORKSPACE: &'static str = "default";
+    pub const OTHER_WORKSPACE: &'static str = "other";
+    pub const MAX_WORKSPACES: usize = 1024;
+
+    /// Bytes delivered so far for a workspace.
+    pub fn get(&self, workspace: &str) -> u64 {
+        self.inner
+            .lock()
+            .unwrap()
+            .get(workspace)
+            .map(|c| c.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Snapshot of all workspace counters.
+    pub fn snapshot(&self) -> HashMap<String, u64> {
+        self.inner
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn counter(&self, workspace: &str) -> Arc<AtomicU64> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(counter) = inner.get(workspace) {
+            return counter.clone();
+        }
+        let key = if inner.len() < Self::MAX_WORKSPACES {
+            workspace
+        } else {
+            Self::OTHER_WORKSPACE
+        };
+        inner.entry(key.to_string()).or_default().clone()
+    }
+}
+
+/// Per-upstream circuit breaker. After `failure_threshold` consecutive connection failures
+/// within `failure_window`, requests to that upstream fail fast with 503 for `cooldown`.
+/// The breaker then goes half-open: exactly one request is let through as a probe while
+/// the rest keep failing fast, and the probe's outcome closes or re-opens the breaker.
+/// A probe that never reports back is replaced after another `cooldown`. A threshold of 0
+/// disables the breaker.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    pub failure_threshold: u32,
+    pub failure_window: Duration,
+    pub cooldown: Duration,
+    state: Arc<Mutex<HashMap<String, BreakerEntry>>>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerEntry {
+    failures: u32,
+    first_failure: Option<Instant>,
+    open_until: Option<Instant>,
+    /// When the half-open probe was let through, while it is in flight
+    probe_since: Option<Instant>,
+}
+
+impl BreakerEntry {
+    /// How long requests are still rejected for, or None if one may go through now
+    /// (as a probe, if the breaker has been open).
+    fn rejecting_for(&self, now: Instant, cooldown: Duration) -> Option<Duration> {
+        let open_until = self.open_until?;
+        if now < open_until {
+            return Some(open_until - now);
+        }
+        let probe_until = self.probe_since? + cooldown;
+        (now < probe_until).then(|| probe_until - now)
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30), Duration::from_secs(10))
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, failure_window: Duration, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            failure_window,
+            cooldown,
+            state: Arc::default(),
+        }
+    }
+
+    /// Whether requests to `upstream` (`host:port`) are currently being rejected. Does not
+    /// change the breaker's state.
+    pub fn is_open(&self, upstream: &str) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .get(upstream)
+            .is_some_and(|entry| entry.rejecting_for(Instant::now(), self.cooldown).is_some())
+    }
+
+    /// Admit a request to `upstream`, or return how long it is still rejected for. Once
+    /// the cooldown has elapsed the admitted request becomes the half-open probe.
+    fn admit(&self, upstream: &str) -> Option<Duration> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.get_mut(upstream)?;
+        let now = Instant::now();
+        if let Some(remaining) = entry.rejecting_for(now, self.cooldown) {
+            return Some(remaining);
+        }
+        if entry.open_until.is_some() {
+            entry.probe_since = Some(now);
+        }
+        None
+    }
+
+    fn record_success(&self, upstream: &str) {
+        self.state.lock().unwrap().remove(upstream);
+    }
+
+    fn record_failure(&self, upstream: &str) {
+        if self.failure_threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let entry = state.entry(upstream.to_string()).or_default();
+        if entry.probe_since.take().is_some() {
+            // The half-open probe failed: open again for a full cooldown
+            entry.open_until = Some(now + self.cooldown);
+            return;
+        }
+        if entry
+            .first_failure
+            .is_none_or(|first| now.duration_since(first) > self.failure_window)
+        {
+            entry.failures = 0;
+            entry.first_failure = Some(now);
+        }
+        entry.failures += 1;
+        if entry.failures >= self.failure_threshold {
+            entry.open_until = Some(now + self.cooldown);
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check(&self, upstream: &str) -> Result<(), Response<BoxBody>> {
+        let Some(remaining) = self.admit(upstream) else {
+            return Ok(());
+        };
+        warn!(%upstream, "upstream circuit open, failing fast");
+        let mut resp = response_with(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "circuit_open: upstream is failing repeatedly, retry later".to_string(),
+        );
+        resp.headers_mut().insert(
+            "x-cmux-upstream-error",
+            HeaderValue::from_static("circuit_open"),
+        );
+        resp.headers_mut().insert(
+            http::header::RETRY_AFTER,
+            HeaderValue::from(remaining.as_secs().max(1)),
+        );
+        Err(resp)
+    }
+
+    fn record<T>(&self, upstream: &str, result: &Result<T, hyper_util::client::legacy::Error>) {
+        match result {
+            Ok(_) => self.record_success(upstream),
+            Err(e) if e.is_connect() => self.record_failure(upstream),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Opt-in in-memory LRU cache for static assets. Only complete `GET` 200 responses the
+/// upstream marks long-lived (`Cache-Control: immutable`, or a `max-age` of at least
+/// `min_max_age`) are kept, keyed by upstream URI, outgoing `Host` and `Accept-Encoding`;
+/// responses that are private, set cookies, or `Vary` on anything but `Accept-Encoding`
+/// are never cached. The cache is bounded by entry count and total body bytes.
+#[derive(Clone, Debug)]
+pub struct AssetCache {
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub min_max_age: Duration,
+    state: Arc<Mutex<AssetCacheState>>,
+}
+
+#[derive(Debug, Default)]
+struct AssetCacheState {
+    entries: HashMap<String, CachedAsset>,
+    /// Keys from least to most recently used.
+    order: VecDeque<String>,
+    bytes: usize,
+}
+
+#[derive(Clone, Debug)]
+struct CachedAsset {
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl Default for AssetCache {
+    fn default() -> Self {
+        Self::new(256, 32 * 1024 * 1024, Duration::from_secs(24 * 60 * 60))
+    }
+}
+
+impl AssetCache {
+    pub fn new(max_entries: usize, max_bytes: usize, min_max_age: Duration) -> Self {
+        Self {
+            max_entries,
+            max_bytes,
+            min_max_age,
+            state: Arc::default(),
+        }
+    }
+
+    /// Number of cached responses.
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Cache key for a proxied request, or None if it must not be served from cache.
+    fn key<B>(req: &Request<B>) -> Option<String> {
+        if req.method() != Method::GET || req.headers().contains_key(http::header::AUTHORIZATION) {
+            return None;
+        }
+        let header = |name| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+        };
+        // Host is the one sent upstream, so virtual hosts on one port stay apart
+        Some(format!(
+            "GET {} {} {}",
+            req.uri(),
+            header(HOST),
+            header(http::header::ACCEPT_ENCODING)
+        ))
+    }
+
+    fn get(&self, key: &str) -> Option<CachedAsset> {
+        let mut state = self.state.lock().unwrap();
+        let asset = state.entries.get(key)?.clone();
+        if let Some(pos) = state.order.iter().position(|k| k == key) {
+            let key = state.order.remove(pos).unwrap();
+            state.order.push_back(key);
+        }
+        Some(asset)
+    }
+
+    /// Whether a response with these headers may be stored.
+    fn is_cacheable(&self, status: StatusCode, headers: &HeaderMap) -> bool {
+        if status != StatusCode::OK || headers.contains_key(http::header::SET_COOKIE) {
+            return false;
+        }
+        let content_length = headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<usize>().ok());
+        if content_length.is_none_or(|len| len > self.max_bytes) {
+            return false;
+        }
+        let vary_ok = headers.get_all(http::header::VARY).iter().all(|v| {
+            v.to_str().is_ok_and(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .all(|field| field.is_empty() || field.eq_ignore_ascii_case("accept-encoding"))
+            })
+        });
+        if !vary_ok {
+            return false;
+        }
+
+        let mut long_lived = false;
+        for directive in headers
+            .get_all(http::header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+        {
+            let directive = directive.trim().to_ascii_lowercase();
+            match directive.split_once('=') {
+                Some(("max-age", secs)) => {
+                    long_lived |= secs
+                        .trim_matches('"')
+                        .parse::<u64>()
+                        .is_ok_and(|secs| secs >= self.min_max_age.as_secs());
+                }
+                Some(("no-cache" | "private", _)) => return false,
+                None if matches!(directive.as_str(), "no-store" | "no-cache" | "private") => {
+                    return false
+                }
+                None if directive == "immutable" => long_lived = true,
+                _ => {}
+            }
+        }
+        long_lived
+    }
+
+    fn insert(&self, key: String, asset: CachedAsset) {
+        let size = asset.body.len();
+        if size > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.remove(&key) {
+            state.bytes -= old.body.len();
+            state.order.retain(|k| *k != key);
+        }
+        while state.entries.len() >= self.max_entries || state.bytes + size > self.max_bytes {
+            let Some(oldest) = state.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = state.entries.remove(&oldest) {
+                state.bytes -= evicted.body.len();
+            }
+        }
+        state.bytes += size;
+        state.order.push_back(key.clone());
+        state.entries.insert(key, asset);
+    }
+}
+
+/// Response body wrapper that adds each data frame's length to a counter as the client reads it.
+struct CountingBody {
+    inner: BoxBody,
+    counter: Arc<AtomicU64>,
+}
+
+impl hyper::body::Body for CountingBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<hyper::body::Frame<Bytes>, BoxError>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let Some(data) = frame.data_ref() {
+                self.counter.fetch_add(data.len() as u64, Ordering::Relaxed);
+            }
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Workspace name patterns a request may target. Patterns are matched against the full
+/// workspace name and may use `*` to match any run of characters. A name matching any
+/// `deny` pattern is rejected; when `allow` is set, a name must also match one of its
+/// patterns. Requests that do not name a workspace are unaffected.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkspaceAcl {
+    pub allow: Option<Vec<String>>,
+    pub deny: Vec<String>,
+}
+
+impl WorkspaceAcl {
+    /// Whether requests may be routed to `workspace`.
+    pub fn permits(&self, workspace: &str) -> bool {
+        if self
+            .deny
+            .iter()
+            .any(|pattern| wildcard_match(pattern, workspace))
+        {
+            return false;
+        }
+        self.allow.as_ref().is_none_or(|allow| {
+            allow
+                .iter()
+                .any(|pattern| wildcard_match(pattern, workspace))
+        })
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any (possibly empty) run of characters.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No `*` in the pattern: exact match
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Front-end HTTP/1 connection options.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Http1Options {
+    /// Keep client connections open between requests.
+    pub keep_alive: bool,
+    /// Forward header names with the casing the client used.
+    pub preserve_header_case: bool,
+    /// Title-case header names the proxy writes itself (e.g. `Content-Length`).
+    pub title_case_headers: bool,
+}
+
+impl Default for Http1Options {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            preserve_header_case: true,
+            title_case_headers: true,
+        }
+    }
+}
+
+/// Time allowed for a proxied HTTP request to get response headers from its upstream.
+/// Upgrades and CONNECT tunnels are not affected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTimeout {
+    /// Timeout when the request carries no usable `X-Cmux-Timeout-Ms`. `None` waits forever.
+    pub default: Option<Duration>,
+    /// Let clients (e.g. the edge) pick the timeout per request with `X-Cmux-Timeout-Ms`.
+    pub honor_header: bool,
+    /// Upper bound for timeouts taken from `X-Cmux-Timeout-Ms`.
+    pub max: Duration,
+}
+
+impl Default for RequestTimeout {
+    fn default() -> Self {
+        Self {
+            default: None,
+            honor_header: false,
+            max: Duration::from_secs(300),
+        }
+    }
+}
+
+impl RequestTimeout {
+    /// Effective timeout for a request with these headers.
+    fn for_request(&self, headers: &HeaderMap) -> Option<Duration> {
+        let requested = headers
+            .get(TIMEOUT_HEADER)
+            .filter(|_| self.honor_header)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&ms| ms > 0)
+            .map(|ms| Duration::from_millis(ms).min(self.max));
+        requested.or(self.default)
+    }
+}
+
+/// Which `Host` header the upstream receives.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum HostPolicy {
+    /// Validate the client's Host and let `X-Cmux-Host-Override` replace it per request.
+    #[default]
+    ForceLocal,
+    /// Forward the client's Host unchanged, for virtual-hosted upstreams. Skips the
+    /// local-host checks and ignores `X-Cmux-Host-Override`.
+    PreserveClient,
+    /// Always send this Host upstream.
+    RewriteTo(String),
+}
+
 #[derive(Clone, Debug)]
 pub struct ProxyConfig {
     pub listen: SocketAddr,
     pub upstream_host: String,
     pub allow_default_upstream: bool,
+    /// Optional path prefix for path-based routing, e.g. `/_cmux`. When set, requests without
+    /// `X-Cmux-Port-Internal` whose path looks like `<prefix>/<port>/...` or
+    /// `<prefix>/<workspace>/<port>/...` are routed as if the headers were present, with the
+    /// prefix stripped from the upstream path.
+    pub path_routing_prefix: Option<String>,
+    /// Per-workspace response byte accounting for proxied HTTP requests.
+    pub byte_counters: ByteCounters,
+    /// Host header policy for proxied HTTP and upgrade requests.
+    pub host_policy: HostPolicy,
+    /// Fast-fail state for upstreams that keep refusing connections.
+    pub circuit_breaker: CircuitBreaker,
+    /// Client addresses allowed to send the [`trusted_headers`](Self::trusted_headers). `None`
+    /// trusts every client; otherwise those headers are stripped from requests whose peer is
+    /// not listed (include `127.0.0.1`/`::1` to trust local clients).
+    pub trusted_clients: Option<Vec<IpAddr>>,
+    /// Internal headers only honored from [`trusted_clients`](Self::trusted_clients), e.g.
+    /// `X-Cmux-Host-Override`, `X-Cmux-Port-Internal`, `X-Cmux-Workspace-Internal` or
+    /// `X-Cmux-Timeout-Ms`. Defaults to [`DEFAULT_TRUSTED_HEADERS`].
+    pub trusted_headers: Vec<String>,
+    /// Maximum bytes carried in each direction of an upgrade or CONNECT tunnel before it is
+    /// torn down. `None` means unlimited.
+    pub tunnel_byte_limit: Option<u64>,
+    /// Workspaces requests may be routed to; others get 403.
+    pub workspace_acl: WorkspaceAcl,
+    /// HTTP/1 keep-alive and header casing for client connections.
+    pub http1: Http1Options,
+    /// Path answered by the proxy itself with a JSON liveness report, regardless of routing
+    /// headers. `None` proxies every path.
+    pub health_path: Option<String>,
+    /// Cache for immutable static asset responses. `None` (the default) disables caching.
+    pub asset_cache: Option<AssetCache>,
+    /// Require a PROXY protocol v1/v2 header at the start of every client connection (when
+    /// behind a TCP load balancer) and use the client address it carries. Connections
+    /// without a valid header are dropped.
+    pub expect_proxy_protocol: bool,
+    /// Per-request upstream timeout, optionally chosen by the client via `X-Cmux-Timeout-Ms`.
+    pub request_timeout: RequestTimeout,
+    /// Headers set on every proxied HTTP and upgrade request (e.g. an internal auth token).
+    /// Client-supplied headers with the same names are dropped first, so clients can't
+    /// spoof them.
+    pub inject_headers: Vec<(HeaderName, HeaderValue)>,
+    /// Largest request head accepted from clients, in bytes. Oversized HTTP/1 heads are
+    /// answered with `431 Request Header Fields Too Large`; HTTP/2 header lists over the
+    /// limit are refused by the codec. HTTP/1 limits below 8 KiB are raised to 8 KiB.
+    pub max_request_header_bytes: usize,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([127, 0, 0, 1], 0)),
+            upstream_host: "127.0.0.1".to_string(),
+            allow_default_upstream: true,
+            path_routing_prefix: None,
+            byte_counters: ByteCounters::default(),
+            host_policy: HostPolicy::default(),
+            circuit_breaker: CircuitBreaker::default(),
+            trusted_clients: None,
+            trusted_headers: DEFAULT_TRUSTED_HEADERS
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+            tunnel_byte_limit: None,
+            workspace_acl: WorkspaceAcl::default(),
+            http1: Http1Options::default(),
+            health_path: Some(DEFAULT_HEALTH_PATH.to_string()),
+            asset_cache: None,
+            expect_proxy_protocol: false,
+            request_timeout: RequestTimeout::default(),
+            inject_headers: Vec::new(),
+            max_request_header_bytes: DEFAULT_MAX_REQUEST_HEADER_BYTES,
+        }
+    }
 }
 
 pub fn spawn_proxy<S>(cfg: ProxyConfig, mut shutdown: S) -> (SocketAddr, JoinHandle<()>)
@@ -311,6 +1087,7 @@ where
     configure_http_client_builder(&mut client_builder);
     let client: Client<HttpConnector, BoxBody> = client_builder.build(connector);
 
+    STARTED_AT.get_or_init(Instant::now);
     let listen = cfg.listen;
     let std_listener = StdTcpListener::bind(listen).expect("bind");
     std_listener.set_nonblocking(true).expect("set nonblocking");
@@ -360,47 +1137,176 @@ pub fn spawn_proxy_multi<S>(
 where
     S: Future<Output = ()> + Send + 'static,
 {
-    // Prepare shared client and shutdown notifier
+    let cfg = ProxyConfig {
+        upstream_host,
+        allow_default_upstream,
+        ..ProxyConfig::default()
+    };
+    spawn_proxy_multi_with_config(listens, cfg, shutdown)
+}
+
+/// Like [`spawn_proxy_multi`], but takes a full [`ProxyConfig`] as a template. The `listen`
+/// field of the template is ignored; each listener gets its own bound address.
+pub fn spawn_proxy_multi_with_config<S>(
+    listens: Vec<SocketAddr>,
+    cfg: ProxyConfig,
+    shutdown: S,
+) -> (Vec<SocketAddr>, JoinHandle<()>)
+where
+    S: Future<Output = ()> + Send + 'static,
+{
+    let (bound_addrs, _ready, handle) = launch_listeners(listens, cfg, shutdown);
+    (bound_addrs, handle)
+}
+
+/// Handle to a proxy started with [`start_proxy`].
+///
+/// Dropping the handle without calling [`ProxyHandle::shutdown`] also stops the proxy.
+#[derive(Debug)]
+pub struct ProxyHandle {
+    addrs: Vec<SocketAddr>,
+    ready: watch::Receiver<bool>,
+    shutdown: oneshot::Sender<()>,
+    join: JoinHandle<()>,
+}
+
+impl ProxyHandle {
+    /// Addresses the proxy actually bound (OS-assigned ports resolved).
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Resolves once every listener has entered its accept loop.
+    pub async fn ready(&self) {
+        let mut ready = self.ready.clone();
+        let _ = ready.wait_for(|ready| *ready).await;
+    }
+
+    /// Stop accepting connections and wait for all listeners to exit.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.join.await;
+    }
+}
+
+/// Start the proxy on multiple addresses and return a [`ProxyHandle`] exposing the bound
+/// addresses, a readiness signal and shutdown. Addresses that fail to bind are skipped.
+pub fn start_proxy(listens: Vec<SocketAddr>, cfg: ProxyConfig) -> ProxyHandle {
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (addrs, ready, join) = launch_listeners(listens, cfg, async move {
+        let _ = shutdown_rx.await;
+    });
+    ProxyHandle {
+        addrs,
+        ready,
+        shutdown: shutdown_tx,
+        join,
+    }
+}
+
+/// Serve the proxy on listeners bound elsewhere, e.g. sockets passed in by systemd (see
+/// [`listeners_from_systemd`]), so restarts never race another process for the port.
+pub fn spawn_proxy_on_listeners<S>(
+    listeners: Vec<StdTcpListener>,
+    cfg: ProxyConfig,
+    shutdown: S,
+) -> (Vec<SocketAddr>, JoinHandle<()>)
+where
+    S: Future<Output = ()> + Send + 'static,
+{
+    let (bound_addrs, _ready, handle) = serve_listeners(listeners, cfg, shutdown);
+    (bound_addrs, handle)
+}
+
+/// Take the listening sockets passed by systemd socket activation (`LISTEN_FDS`, starting
+/// at fd 3). Returns an empty list when the variables are absent or meant for another
+/// process (`LISTEN_PID`). The descriptors are only handed out once: later calls return
+/// an empty list, and the variables are removed so child processes don't inherit them.
+#[cfg(unix)]
+pub fn listeners_from_systemd() -> Vec<StdTcpListener> {
+    use std::os::fd::FromRawFd;
+
+    const SD_LISTEN_FDS_START: i32 = 3;
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    if TAKEN.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let count = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse::<i32>().ok())
+        .unwrap_or(0);
+    for var in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    if !for_us {
+        return Vec::new();
+    }
+    (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count.max(0))
+        // SAFETY: systemd hands these descriptors to this process, and TAKEN makes this
+        // the only place that ever wraps them, so each gets exactly one owner.
+        .map(|fd| unsafe { StdTcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+fn launch_listeners<S>(
+    listens: Vec<SocketAddr>,
+    cfg: ProxyConfig,
+    shutdown: S,
+) -> (Vec<SocketAddr>, watch::Receiver<bool>, JoinHandle<()>)
+where
+    S: Future<Output = ()> + Send + 'static,
+{
+    let mut std_listeners = Vec::new();
+    for addr in listens {
+        match StdTcpListener::bind(addr) {
+            Ok(listener) => std_listeners.push(listener),
+            Err(e) => error!(%e, "failed to bind to {}", addr),
+        }
+    }
+    serve_listeners(std_listeners, cfg, shutdown)
+}
+
+fn serve_listeners<S>(
+    std_listeners: Vec<StdTcpListener>,
+    cfg: ProxyConfig,
+    shutdown: S,
+) -> (Vec<SocketAddr>, watch::Receiver<bool>, JoinHandle<()>)
+where
+    S: Future<Output = ()> + Send + 'static,
+{
+    // Prepare shared client and shutdown signal. A watch channel (rather than Notify) means a
+    // listener that has not reached its select yet still observes an earlier shutdown.
     let mut connector = HttpConnector::new();
     connector.set_connect_timeout(Some(Duration::from_secs(5)));
     let mut client_builder = Client::builder(TokioExecutor::new());
     configure_http_client_builder(&mut client_builder);
     let client: Client<HttpConnector, BoxBody> = client_builder.build(connector);
 
-    let notify = Arc::new(Notify::new());
-    let notify_clone = notify.clone();
+    STARTED_AT.get_or_init(Instant::now);
+    let (stop_tx, stop_rx) = watch::channel(false);
     tokio::spawn(async move {
         shutdown.await;
-        notify_clone.notify_waiters();
+        let _ = stop_tx.send(true);
     });
 
-    let mut join_set: JoinSet<()> = JoinSet::new();
-    let mut bound_addrs = Vec::new();
-
-    for addr in listens {
-        let client = client.clone();
-        let upstream = upstream_host.clone();
-        let notify = notify.clone();
-        let allow_default = allow_default_upstream;
-
-        let std_listener = match StdTcpListener::bind(addr) {
-            Ok(listener) => listener,
+    let mut listeners = Vec::new();
+    for std_listener in std_listeners {
+        let actual_addr = match std_listener.local_addr() {
+            Ok(addr) => addr,
             Err(e) => {
-                error!(%e, "failed to bind to {}", addr);
+                error!(%e, "failed to get local addr of listener");
                 continue;
             }
         };
         if let Err(e) = std_listener.set_nonblocking(true) {
-            error!(%e, "failed to set nonblocking on {}", addr);
+            error!(%e, "failed to set nonblocking on {}", actual_addr);
             continue;
         }
-        let actual_addr = match std_listener.local_addr() {
-            Ok(addr) => addr,
-            Err(e) => {
-                error!(%e, "failed to get local addr for {}", addr);
-                continue;
-            }
-        };
         let listener = match TcpListener::from_std(std_listener) {
             Ok(listener) => listener,
             Err(e) => {
@@ -408,11 +1314,30 @@ where
                 continue;
             }
         };
+        listeners.push((actual_addr, listener));
+    }
 
-        bound_addrs.push(actual_addr);
+    let bound_addrs: Vec<SocketAddr> = listeners.iter().map(|(addr, _)| *addr).collect();
+    let (ready_tx, ready_rx) = watch::channel(listeners.is_empty());
+    let ready_tx = Arc::new(ready_tx);
+    let pending = Arc::new(AtomicUsize::new(listeners.len()));
+    let mut join_set: JoinSet<()> = JoinSet::new();
+
+    for (actual_addr, listener) in listeners {
+        let client = client.clone();
+        let mut stop = stop_rx.clone();
+        let ready_tx = ready_tx.clone();
+        let pending = pending.clone();
+        let cfg = ProxyConfig {
+            listen: actual_addr,
+            ..cfg.clone()
+        };
 
         join_set.spawn(async move {
             info!("proxy listening on {}", actual_addr);
+            if pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                ready_tx.send_replace(true);
+            }
 
             loop {
                 tokio::select! {
@@ -420,14 +1345,9 @@ where
                         match result {
                             Ok((stream, remote_addr)) => {
                                 let client = client.clone();
-                                let upstream = upstream.clone();
+                                let cfg = cfg.clone();
 
                                 tokio::spawn(async move {
-                                    let cfg = ProxyConfig {
-                                        listen: actual_addr,
-                                        upstream_host: upstream.clone(),
-                                        allow_default_upstream: allow_default,
-                                    };
                                     if let Err(err) =
                                         serve_client_stream(stream, remote_addr, client, cfg).await
                                     {
@@ -440,7 +1360,7 @@ where
                             }
                         }
                     }
-                    _ = notify.notified() => {
+                    _ = stop.wait_for(|stop| *stop) => {
                         info!("shutting down proxy on {}", actual_addr);
                         break;
                     }
@@ -451,15 +1371,26 @@ where
 
     let handle = tokio::spawn(async move { while let Some(_res) = join_set.join_next().await {} });
 
-    (bound_addrs, handle)
+    (bound_addrs, ready_rx, handle)
 }
 
 async fn serve_client_stream(
-    stream: TcpStream,
-    remote_addr: SocketAddr,
+    mut stream: TcpStream,
+    mut remote_addr: SocketAddr,
     client: Client<HttpConnector, BoxBody>,
     cfg: ProxyConfig,
 ) -> Result<(), BoxError> {
+    if cfg.expect_proxy_protocol {
+        let header = tokio::time::timeout(
+            Duration::from_secs(PROXY_HEADER_TIMEOUT_SECS),
+            read_proxy_protocol_header(&mut stream),
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no PROXY protocol header"))?;
+        if let Some(client_addr) = header? {
+            remote_addr = client_addr;
+        }
+    }
     let (buffered_stream, client_prefers_http2) = sniff_http2_preface(stream).await?;
     let io = TokioIo::new(buffered_stream);
     let svc_client = client.clone();
@@ -469,20 +1400,107 @@ async fn serve_client_stream(
 
     if client_prefers_http2 {
         let mut builder = http2::Builder::new(TokioExecutor::new());
-        configure_http2_server_builder(&mut builder);
+        configure_http2_server_builder(&mut builder, cfg.max_request_header_bytes);
         builder.timer(TokioTimer::new());
         builder.serve_connection(io, service).await?;
     } else {
         let mut builder = http1::Builder::new();
-        configure_http1_server_builder(&mut builder);
-        builder
-            .serve_connection(io, service)
-            .with_upgrades()
-            .await?;
+        configure_http1_server_builder(&mut builder, &cfg.http1, cfg.max_request_header_bytes);
+        let served = builder.serve_connection(io, service).with_upgrades().await;
+        match served {
+            // hyper has already written the 431; this is a rejection, not a broken connection
+            Err(err) if err.is_parse_too_large() => {
+                warn!(
+                    client = %remote_addr,
+                    limit = cfg.max_request_header_bytes,
+                    "rejected oversized request headers with 431"
+                );
+            }
+            other => other?,
+        }
     }
     Ok(())
 }
 
+/// Consume a PROXY protocol v1 or v2 header from the start of a connection and return the
+/// client address it carries. `Ok(None)` means the balancer sent no address (v1 `UNKNOWN`,
+/// v2 `LOCAL` or a non-IP family), so the socket peer stands.
+async fn read_proxy_protocol_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> io::Result<Option<SocketAddr>> {
+    let invalid =
+        |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("PROXY protocol: {msg}"));
+
+    let mut head = [0u8; 16];
+    stream.read_exact(&mut head[..5]).await?;
+    if &head[..5] == b"PROXY" {
+        let mut line = head[..5].to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= PROXY_V1_MAX_LEN {
+                return Err(invalid("v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        return std::str::from_utf8(&line[..line.len() - 2])
+            .ok()
+            .and_then(parse_proxy_v1)
+            .ok_or_else(|| invalid("malformed v1 header"));
+    }
+    if head[..5] != PROXY_V2_SIGNATURE[..5] {
+        return Err(invalid("missing header"));
+    }
+
+    stream.read_exact(&mut head[5..]).await?;
+    if head[..12] != PROXY_V2_SIGNATURE[..] || head[12] >> 4 != 2 {
+        return Err(invalid("malformed v2 header"));
+    }
+    let mut addrs = vec![0u8; u16::from_be_bytes([head[14], head[15]]) as usize];
+    stream.read_exact(&mut addrs).await?;
+    match head[12] & 0x0F {
+        // LOCAL: the balancer's own connection, e.g. a health check
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unknown v2 command")),
+    }
+    let port = |at: usize| u16::from_be_bytes([addrs[at], addrs[at + 1]]);
+    match head[13] >> 4 {
+        // AF_INET: src(4) dst(4) src_port(2) dst_port(2)
+        1 if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[..4].try_into().unwrap();
+            Ok(Some(SocketAddr::from((ip, port(8)))))
+        }
+        // AF_INET6: src(16) dst(16) src_port(2) dst_port(2)
+        2 if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[..16].try_into().unwrap();
+            Ok(Some(SocketAddr::from((ip, port(32)))))
+        }
+        1 | 2 => Err(invalid("truncated v2 address block")),
+        _ => Ok(None),
+    }
+}
+
+/// Parse a v1 header line without its CRLF, e.g. `PROXY TCP4 1.2.3.4 5.6.7.8 4000 80`.
+fn parse_proxy_v1(line: &str) -> Option<Option<SocketAddr>> {
+    let mut fields = line.split(' ');
+    if fields.next()? != "PROXY" {
+        return None;
+    }
+    let proto = fields.next()?;
+    if proto == "UNKNOWN" {
+        return Some(None);
+    }
+    let src: IpAddr = fields.next()?.parse().ok()?;
+    let _dst: IpAddr = fields.next()?.parse().ok()?;
+    let src_port: u16 = fields.next()?.parse().ok()?;
+    let _dst_port: u16 = fields.next()?.parse().ok()?;
+    let family_ok = match proto {
+        "TCP4" => src.is_ipv4(),
+        "TCP6" => src.is_ipv6(),
+        _ => false,
+    };
+    (family_ok && fields.next().is_none()).then_some(Some(SocketAddr::new(src, src_port)))
+}
+
 async fn sniff_http2_preface(stream: TcpStream) -> io::Result<(BufferedStream, bool)> {
     let mut buffer: Vec<u8> = Vec::new();
     let mut temp = [0u8; 24];
@@ -590,7 +1608,19 @@ fn upstream_host_from_headers(
     headers: &HeaderMap,
     default_host: &str,
     allow_default_without_workspace: bool,
+    acl: &WorkspaceAcl,
 ) -> Result<String, Response<BoxBody>> {
+    let check_acl = |ws: &str| {
+        if acl.permits(ws) {
+            Ok(())
+        } else {
+            Err(response_with(
+                StatusCode::FORBIDDEN,
+                format!("workspace not allowed: {}", ws),
+            ))
+        }
+    };
+
     const HDR_WS: &str = "X-Cmux-Workspace-Internal";
     if let Some(val) = headers.get(HDR_WS) {
         let v = val.to_str().map_err(|_| {
@@ -606,6 +1636,7 @@ fn upstream_host_from_headers(
                 format!("{} cannot be empty", HDR_WS),
             ));
         }
+        check_acl(ws)?;
         let ip = workspace_ip_from_name(ws).ok_or_else(|| {
             response_with(
                 StatusCode::BAD_REQUEST,
@@ -621,6 +1652,7 @@ fn upstream_host_from_headers(
 
     // Fallback: try parsing from subdomain pattern if present
     if let Some((ws, _port)) = parse_workspace_port_from_host(headers) {
+        check_acl(&ws)?;
         if let Some(ip) = workspace_ip_from_name(&ws) {
             return Ok(ip.to_string());
         } else {
@@ -631,7 +1663,26 @@ fn upstream_host_from_headers(
         }
     }
 
-    Ok(default_host.to_string())
+    Err(response_with(
+        StatusCode::BAD_REQUEST,
+        format!(
+            "missing workspace: set {} or use a <workspace>-<port>.localhost host \
+             (routing to the default upstream is disabled)",
+            HDR_WS
+        ),
+    ))
+}
+
+/// Workspace name used for accounting: the workspace header, then the `<ws>-<port>.localhost`
+/// host pattern, falling back to [`ByteCounters::DEFAULT_WORKSPACE`].
+fn workspace_key_from_headers(headers: &HeaderMap) -> String {
+    headers
+        .get("X-Cmux-Workspace-Internal")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .or_else(|| parse_workspace_port_from_host(headers).map(|(ws, _)| ws))
+        .unwrap_or_else(|| ByteCounters::DEFAULT_WORKSPACE.to_string())
 }
 
 fn is_upgrade_request(req: &Request<Incoming>) -> bool {
@@ -649,7 +1700,50 @@ fn is_upgrade_request(req: &Request<Incoming>) -> bool {
     has_conn_upgrade && has_upgrade_hdr
 }
 
+/// Whether the client expects the connection to close after this response: an HTTP/1.0
+/// request without `Connection: keep-alive`, or any request with `Connection: close`.
+fn wants_connection_close(req: &Request<Incoming>) -> bool {
+    let has_token = |token: &str| {
+        req.headers()
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    has_token("close") || (req.version() <= Version::HTTP_10 && !has_token("keep-alive"))
+}
+
+/// Replace any client-supplied values of the configured header names with the
+/// configured values.
+fn inject_headers(headers: &mut HeaderMap, inject: &[(HeaderName, HeaderValue)]) {
+    for (name, _) in inject {
+        headers.remove(name);
+    }
+    for (name, value) in inject {
+        headers.append(name, value.clone());
+    }
+}
+
+/// Header names listed in `Connection: <header-names>`, which are hop-by-hop too.
+fn connection_nominated(h: &HeaderMap) -> Vec<String> {
+    h.get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Must run before the proxy sets any header of its own, or a client could delete it by
+/// naming it in `Connection`.
 fn strip_hop_by_hop_headers(h: &mut HeaderMap) {
+    // Read the nominated headers before Connection itself goes
+    for name in connection_nominated(h) {
+        h.remove(name.as_str());
+    }
+
     // Standard hop-by-hop headers per RFC 7230
     const HOP_HEADERS: &[&str] = &[
         "connection",
@@ -668,20 +1762,6 @@ fn strip_hop_by_hop_headers(h: &mut HeaderMap) {
     for name in HOP_HEADERS {
         h.remove(*name);
     }
-
-    // Also remove headers listed in Connection: <header-names>
-    if let Some(conn_val) = h
-        .get(CONNECTION)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.to_string())
-    {
-        for token in conn_val.split(',') {
-            let name = token.trim().to_ascii_lowercase();
-            if !name.is_empty() {
-                h.remove(&name);
-            }
-        }
-    }
 }
 
 #[allow(clippy::result_large_err)]
@@ -733,6 +1813,120 @@ fn parse_workspace_port_from_host(headers: &HeaderMap) -> Option<(String, u16)>
     Some((ws_part.to_string(), port))
 }
 
+/// HTTP/1.x absolute-form request targets (`GET http://host:port/path`): per RFC 9112 the target authority
+/// replaces the Host header, and an explicit port in it selects the upstream port unless
+/// `X-Cmux-Port-Internal` names a different one, which is rejected as ambiguous. The URI is then
+/// reduced to origin-form so the rest of the pipeline only ever sees a path.
+#[allow(clippy::result_large_err)]
+fn apply_absolute_form(headers: &mut HeaderMap, uri: &mut Uri) -> Result<(), Response<BoxBody>> {
+    let authority = match (uri.scheme(), uri.authority()) {
+        (Some(_), Some(authority)) => authority.clone(),
+        _ => return Ok(()),
+    };
+
+    let host_value = HeaderValue::from_str(authority.as_str()).map_err(|_| {
+        response_with(
+            StatusCode::BAD_REQUEST,
+            "invalid authority in request target".to_string(),
+        )
+    })?;
+    headers.insert(HOST, host_value);
+
+    let target_port = parse_workspace_port_from_host(headers)
+        .map(|(_, port)| port)
+        .or_else(|| authority.port_u16());
+    let header_port = headers
+        .get("x-cmux-port-internal")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.trim().parse::<u16>().ok());
+    match (target_port, header_port) {
+        (Some(target), Some(header)) if target != header => {
+            return Err(response_with(
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "request target port {} conflicts with X-Cmux-Port-Internal {}",
+                    target, header
+                ),
+            ));
+        }
+        (Some(target), None) if !headers.contains_key("x-cmux-port-internal") => {
+            headers.insert("x-cmux-port-internal", HeaderValue::from(target));
+        }
+        _ => {}
+    }
+
+    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+    *uri = Uri::from_str(path_and_query).map_err(|_| {
+        response_with(
+            StatusCode::BAD_REQUEST,
+            "invalid path in request target".to_string(),
+        )
+    })?;
+    Ok(())
+}
+
+/// Path-based routing: if `X-Cmux-Port-Internal` is absent and the request path starts with the
+/// configured prefix, extract `[<workspace>/]<port>` from the path, inject the equivalent internal
+/// headers and strip the routing segments from the URI so the rest of the pipeline is unchanged.
+#[allow(clippy::result_large_err)]
+fn apply_path_routing(
+    prefix: &str,
+    headers: &mut HeaderMap,
+    uri: &mut Uri,
+) -> Result<(), Response<BoxBody>> {
+    if headers.contains_key("x-cmux-port-internal") {
+        return Ok(());
+    }
+    let prefix = prefix.trim_end_matches('/');
+    let path = uri.path().to_string();
+    let rest = match path.strip_prefix(prefix) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => return Ok(()),
+    };
+
+    let mut segments = rest.trim_start_matches('/').splitn(2, '/');
+    let first = segments.next().unwrap_or("");
+    let remainder = segments.next();
+    let (workspace, port_str, remainder) = if !first.is_empty() && first.parse::<u16>().is_err() {
+        let mut segments = remainder.unwrap_or("").splitn(2, '/');
+        let port_str = segments.next().unwrap_or("");
+        (Some(first), port_str, segments.next())
+    } else {
+        (None, first, remainder)
+    };
+
+    let port: u16 = port_str.parse().map_err(|_| {
+        response_with(
+            StatusCode::BAD_REQUEST,
+            format!("invalid port in routing path: {}", prefix),
+        )
+    })?;
+
+    let mut new_pq = format!("/{}", remainder.unwrap_or(""));
+    if let Some(query) = uri.query() {
+        new_pq.push('?');
+        new_pq.push_str(query);
+    }
+    *uri = Uri::from_str(&new_pq).map_err(|_| {
+        response_with(
+            StatusCode::BAD_REQUEST,
+            "invalid path after routing prefix".to_string(),
+        )
+    })?;
+
+    headers.insert("x-cmux-port-internal", HeaderValue::from(port));
+    if let Some(ws) = workspace {
+        let value = HeaderValue::from_str(ws).map_err(|_| {
+            response_with(
+                StatusCode::BAD_REQUEST,
+                format!("invalid workspace name: {}", ws),
+            )
+        })?;
+        headers.insert("x-cmux-workspace-internal", value);
+    }
+    Ok(())
+}
+
 #[allow(clippy::result_large_err)]
 fn enforce_local_host_header(
     headers: &HeaderMap,
@@ -754,6 +1948,136 @@ fn enforce_local_host_header(
     Ok(())
 }
 
+fn client_trusted(allowlist: Option<&[IpAddr]>, peer: IpAddr) -> bool {
+    let peer = peer.to_canonical();
+    allowlist.is_none_or(|list| list.iter().any(|ip| ip.to_canonical() == peer))
+}
+
+/// Host to send upstream in place of the client's, according to the configured policy.
+/// `None` forwards the client's Host unchanged.
+#[allow(clippy::result_large_err)]
+fn resolve_host_override(
+    policy: &HostPolicy,
+    headers: &HeaderMap,
+) -> Result<Option<String>, Response<BoxBody>> {
+    match policy {
+        HostPolicy::ForceLocal => {
+            let host_override = headers
+                .get(HOST_OVERRIDE_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty());
+            enforce_local_host_header(headers, host_override.as_deref())?;
+            Ok(host_override)
+        }
+        HostPolicy::PreserveClient => Ok(None),
+        HostPolicy::RewriteTo(host) => Ok(Some(host.clone())),
+    }
+}
+
+/// Stable category for an upstream failure, returned to clients instead of the raw error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UpstreamErrorKind {
+    ConnectionRefused,
+    Resolve,
+    Timeout,
+    Tls,
+    Protocol,
+    Other,
+}
+
+impl UpstreamErrorKind {
+    fn code(self) -> &'static str {
+        match self {
+            UpstreamErrorKind::ConnectionRefused => "connection_refused",
+            UpstreamErrorKind::Resolve => "resolve_failed",
+            UpstreamErrorKind::Timeout => "timeout",
+            UpstreamErrorKind::Tls => "tls_error",
+            UpstreamErrorKind::Protocol => "protocol_error",
+            UpstreamErrorKind::Other => "upstream_error",
+        }
+    }
+
+    fn message(self) -> &'static str {
+        match self {
+            UpstreamErrorKind::ConnectionRefused => "upstream refused the connection",
+            UpstreamErrorKind::Resolve => "could not resolve upstream host",
+            UpstreamErrorKind::Timeout => "timed out connecting to upstream",
+            UpstreamErrorKind::Tls => "TLS error talking to upstream",
+            UpstreamErrorKind::Protocol => "upstream sent an invalid response",
+            UpstreamErrorKind::Other => "upstream request failed",
+        }
+    }
+}
+
+/// Walk an error's source chain to find a stable category for it.
+fn classify_upstream_error(err: &(dyn std::error::Error + 'static)) -> UpstreamErrorKind {
+    let mut current: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = current {
+        if let Some(io_err) = e.downcast_ref::<io::Error>() {
+            match io_err.kind() {
+                io::ErrorKind::ConnectionRefused => return UpstreamErrorKind::ConnectionRefused,
+                io::ErrorKind::TimedOut => return UpstreamErrorKind::Timeout,
+                _ => {}
+            }
+        }
+        if let Some(hyper_err) = e.downcast_ref::<hyper::Error>() {
+            if hyper_err.is_timeout() {
+                return UpstreamErrorKind::Timeout;
+            }
+            if hyper_err.is_parse() || hyper_err.is_incomplete_message() {
+                return UpstreamErrorKind::Protocol;
+            }
+        }
+        let text = e.to_string().to_ascii_lowercase();
+        if text.contains("dns error") || text.contains("failed to lookup") {
+            return UpstreamErrorKind::Resolve;
+        }
+        if text.contains("tls") || text.contains("certificate") {
+            return UpstreamErrorKind::Tls;
+        }
+        current = e.source();
+    }
+    UpstreamErrorKind::Other
+}
+
+/// Build a 502 for an upstream failure. The detailed error is logged, only the category
+/// and a generic message are returned to the client.
+fn upstream_error_response(
+    err: &(dyn std::error::Error + 'static),
+    context: &str,
+) -> Response<BoxBody> {
+    let kind = classify_upstream_error(err);
+    warn!(error = %err, category = kind.code(), "{}", context);
+    let mut resp = response_with(
+        StatusCode::BAD_GATEWAY,
+        format!("{}: {}", kind.code(), kind.message()),
+    );
+    resp.headers_mut().insert(
+        "x-cmux-upstream-error",
+        HeaderValue::from_static(kind.code()),
+    );
+    resp
+}
+
+/// Build a 504 for a request whose upstream did not respond within its timeout.
+fn upstream_timeout_response(timeout: Duration) -> Response<BoxBody> {
+    warn!(
+        timeout_ms = timeout.as_millis() as u64,
+        "upstream request timed out"
+    );
+    let kind = UpstreamErrorKind::Timeout;
+    let mut resp = response_with(
+        StatusCode::GATEWAY_TIMEOUT,
+        format!("{}: upstream did not respond in time", kind.code()),
+    );
+    resp.headers_mut().insert(
+        "x-cmux-upstream-error",
+        HeaderValue::from_static(kind.code()),
+    );
+    resp
+}
+
 fn response_with(status: StatusCode, msg: String) -> Response<BoxBody> {
     Response::builder()
         .status(status)
@@ -762,13 +2086,55 @@ fn response_with(status: StatusCode, msg: String) -> Response<BoxBody> {
         .unwrap()
 }
 
+fn health_response() -> Response<BoxBody> {
+    let uptime = STARTED_AT.get_or_init(Instant::now).elapsed().as_secs();
+    let body = format!(
+        r#"{{"status":"ok","version":"{}","uptime_secs":{}}}"#,
+        env!("CARGO_PKG_VERSION"),
+        uptime
+    );
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("content-type", "application/json")
+        .header("cache-control", "no-store")
+        .body(full_body(body))
+        .unwrap()
+}
+
 async fn handle(
     client: Client<HttpConnector, BoxBody>,
     cfg: ProxyConfig,
     remote_addr: SocketAddr,
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
 ) -> Result<Response<BoxBody>, Infallible> {
     let method = req.method().clone();
+    if method != Method::CONNECT && cfg.health_path.as_deref() == Some(req.uri().path()) {
+        return Ok(health_response());
+    }
+    if !client_trusted(cfg.trusted_clients.as_deref(), remote_addr.ip()) {
+        for name in &cfg.trusted_headers {
+            if req.headers_mut().remove(name.as_str()).is_some() {
+                warn!(client = %remote_addr, header = %name, "ignoring trusted header from untrusted client");
+            }
+        }
+    }
+    if method != Method::CONNECT {
+        // HTTP/2 URIs always carry :authority, which is not an absolute-form target
+        if req.version() <= Version::HTTP_11 {
+            let mut uri = req.uri().clone();
+            if let Err(resp) = apply_absolute_form(req.headers_mut(), &mut uri) {
+                return Ok(resp);
+            }
+            *req.uri_mut() = uri;
+        }
+        if let Some(prefix) = cfg.path_routing_prefix.as_deref() {
+            let mut uri = req.uri().clone();
+            if let Err(resp) = apply_path_routing(prefix, req.headers_mut(), &mut uri) {
+                return Ok(resp);
+            }
+            *req.uri_mut() = uri;
+        }
+    }
     let is_upgrade = is_upgrade_request(&req);
 
     match method {
@@ -783,10 +2149,16 @@ async fn handle(
                     Err(resp) => Ok(resp),
                 }
             } else {
-                match handle_http(client, &cfg, remote_addr, req).await {
-                    Ok(resp) => Ok(resp),
-                    Err(resp) => Ok(resp),
+                // Upstream always gets HTTP/1.1; the client side keeps its own
+                // connection semantics
+                let close = wants_connection_close(&req);
+                let (Ok(mut resp) | Err(mut resp)) =
+                    handle_http(client, &cfg, remote_addr, req).await;
+                if close {
+                    resp.headers_mut()
+                        .insert(CONNECTION, HeaderValue::from_static("close"));
                 }
+                Ok(resp)
             }
         }
     }
@@ -805,17 +2177,16 @@ async fn handle_http(
         &parts.headers,
         &cfg.upstream_host,
         cfg.allow_default_upstream,
+        &cfg.workspace_acl,
     )?;
-    let host_override = parts
-        .headers
-        .get(HOST_OVERRIDE_HEADER)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty());
-    enforce_local_host_header(&parts.headers, host_override.as_deref())?;
+    let host_override = resolve_host_override(&cfg.host_policy, &parts.headers)?;
+    let workspace_key = workspace_key_from_headers(&parts.headers);
+    let timeout = cfg.request_timeout.for_request(&parts.headers);
 
     parts.uri = build_upstream_uri(&upstream_host, port, &parts.uri)?;
     parts.version = Version::HTTP_11;
+    // Strip hop-by-hop headers before any proxy-owned header is set below
+    strip_hop_by_hop_headers(&mut parts.headers);
 
     // Convert incoming body to BoxBody
     let proxied_body: BoxBody = incoming_to_box(incoming);
@@ -825,14 +2196,20 @@ async fn handle_http(
     new_req.headers_mut().remove("x-cmux-port-internal");
     new_req.headers_mut().remove("x-cmux-workspace-internal");
     new_req.headers_mut().remove(HOST_OVERRIDE_HEADER);
+    new_req.headers_mut().remove(TIMEOUT_HEADER);
     if let Some(host) = host_override.as_ref() {
         if let Ok(value) = HeaderValue::from_str(host.as_str()) {
             new_req.headers_mut().insert(HOST, value);
         }
     }
+    if let Some(timeout) = timeout {
+        new_req.headers_mut().insert(
+            FORWARDED_TIMEOUT_HEADER,
+            HeaderValue::from(timeout.as_millis() as u64),
+        );
+    }
 
-    // Strip hop-by-hop headers on the proxied request
-    strip_hop_by_hop_headers(new_req.headers_mut());
+    inject_headers(new_req.headers_mut(), &cfg.inject_headers);
 
     info!(
         client = %remote_addr,
@@ -843,12 +2220,33 @@ async fn handle_http(
         "proxy http"
     );
 
-    let upstream_resp = client.request(new_req).await.map_err(|e| {
-        response_with(
-            StatusCode::BAD_GATEWAY,
-            format!("upstream request error: {}", e),
-        )
-    })?;
+    let cache = cfg
+        .asset_cache
+        .as_ref()
+        .and_then(|cache| Some((cache, AssetCache::key(&new_req)?)));
+    if let Some(asset) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        let counter = cfg.byte_counters.counter(&workspace_key);
+        counter.fetch_add(asset.body.len() as u64, Ordering::Relaxed);
+        let mut resp = Response::new(full_body(asset.body));
+        *resp.headers_mut() = asset.headers;
+        return Ok(resp);
+    }
+
+    let upstream_key = format!("{}:{}", upstream_host, port);
+    cfg.circuit_breaker.check(&upstream_key)?;
+    let upstream_resp = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, client.request(new_req))
+            .await
+            .map_err(|_| {
+                // A hanging upstream counts against the breaker like a refusing one
+                cfg.circuit_breaker.record_failure(&upstream_key);
+                upstream_timeout_response(timeout)
+            })?,
+        None => client.request(new_req).await,
+    };
+    cfg.circuit_breaker.record(&upstream_key, &upstream_resp);
+    let upstream_resp =
+        upstream_resp.map_err(|e| upstream_error_response(&e, "upstream request error"))?;
 
     // Map upstream response back to client, stripping hop-by-hop headers
     let mut client_resp_builder = Response::builder().status(upstream_resp.status());
@@ -861,7 +2259,36 @@ async fn handle_http(
     }
     strip_hop_by_hop_headers(headers);
 
-    let body = incoming_to_box(upstream_resp.into_body());
+    if let Some((cache, key)) = cache {
+        if cache.is_cacheable(upstream_resp.status(), headers) {
+            // Buffer the (bounded) body so it can be stored and replayed
+            let body = incoming_to_box(upstream_resp.into_body())
+                .collect()
+                .await
+                .map_err(|e| upstream_error_response(e.as_ref(), "upstream body error"))?
+                .to_bytes();
+            cfg.byte_counters
+                .counter(&workspace_key)
+                .fetch_add(body.len() as u64, Ordering::Relaxed);
+            let asset = CachedAsset {
+                headers: headers.clone(),
+                body: body.clone(),
+            };
+            cache.insert(key, asset);
+            return client_resp_builder.body(full_body(body)).map_err(|_| {
+                response_with(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "failed to build response".into(),
+                )
+            });
+        }
+    }
+
+    let body = CountingBody {
+        inner: incoming_to_box(upstream_resp.into_body()),
+        counter: cfg.byte_counters.counter(&workspace_key),
+    }
+    .boxed();
     let resp = client_resp_builder.body(body).map_err(|_| {
         response_with(
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -885,15 +2312,10 @@ async fn handle_upgrade(
         req.headers(),
         &cfg.upstream_host,
         cfg.allow_default_upstream,
+        &cfg.workspace_acl,
     )?;
     let upstream_uri = build_upstream_uri(&upstream_host, port, req.uri())?;
-    let host_override = req
-        .headers()
-        .get(HOST_OVERRIDE_HEADER)
-        .and_then(|v| v.to_str().ok())
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty());
-    enforce_local_host_header(req.headers(), host_override.as_deref())?;
+    let host_override = resolve_host_override(&cfg.host_policy, req.headers())?;
 
     // Build proxied request for upstream - need to clone headers before consuming req
     let mut proxied_req_builder = Request::builder()
@@ -908,6 +2330,7 @@ async fn handle_upgrade(
                 .as_str()
                 .eq_ignore_ascii_case("x-cmux-workspace-internal")
             && !name.as_str().eq_ignore_ascii_case(HOST_OVERRIDE_HEADER)
+            && !name.as_str().eq_ignore_ascii_case(TIMEOUT_HEADER)
         {
             proxied_req_builder = proxied_req_builder.header(name, value);
         }
@@ -922,7 +2345,13 @@ async fn handle_upgrade(
         )
     })?;
 
-    // Do NOT strip upgrade/connection here; upstream needs them
+    // Do NOT strip upgrade/connection here; upstream needs them. Other headers the client
+    // nominated in Connection go before any proxy-owned header is set.
+    for name in connection_nominated(proxied_req.headers()) {
+        if name != "upgrade" && name != "connection" {
+            proxied_req.headers_mut().remove(name.as_str());
+        }
+    }
     proxied_req.headers_mut().remove("proxy-connection");
     proxied_req.headers_mut().remove("keep-alive");
     proxied_req.headers_mut().remove("te");
@@ -933,16 +2362,17 @@ async fn handle_upgrade(
             proxied_req.headers_mut().insert(HOST, value);
         }
     }
+    inject_headers(proxied_req.headers_mut(), &cfg.inject_headers);
 
     info!(client = %remote_addr, port = port, upstream = %upstream_host, "proxy upgrade (e.g. websocket)");
 
     // Send to upstream and get its response (should be 101)
-    let upstream_resp = client.request(proxied_req).await.map_err(|e| {
-        response_with(
-            StatusCode::BAD_GATEWAY,
-            format!("upstream upgrade error: {}", e),
-        )
-    })?;
+    let upstream_key = format!("{}:{}", upstream_host, port);
+    cfg.circuit_breaker.check(&upstream_key)?;
+    let upstream_resp = client.request(proxied_req).await;
+    cfg.circuit_breaker.record(&upstream_key, &upstream_resp);
+    let upstream_resp =
+        upstream_resp.map_err(|e| upstream_error_response(&e, "upstream upgrade error"))?;
 
     if upstream_resp.status() != StatusCode::SWITCHING_PROTOCOLS {
         // Return upstream status (probably 4xx/5xx) to client with body
@@ -984,6 +2414,7 @@ async fn handle_upgrade(
     let original_req = Request::from_parts(parts, ());
 
     // Spawn tunnel after returning the 101 to the client
+    let limit = cfg.tunnel_byte_limit;
     tokio::spawn(async move {
         match future::try_join(
             hyper::upgrade::on(original_req),
@@ -992,8 +2423,8 @@ async fn handle_upgrade(
         .await
         {
             Ok((client_upgraded, upstream_upgraded)) => {
-                let mut client_io = TokioIo::new(client_upgraded);
-                let mut upstream_io = TokioIo::new(upstream_upgraded);
+                let mut client_io = CappedIo::new(TokioIo::new(client_upgraded), limit);
+                let mut upstream_io = CappedIo::new(TokioIo::new(upstream_upgraded), limit);
                 if let Err(e) = copy_bidirectional(&mut client_io, &mut upstream_io).await {
                     warn!(%e, "upgrade tunnel error");
                 }
@@ -1020,9 +2451,13 @@ async fn handle_connect(
         req.headers(),
         &cfg.upstream_host,
         cfg.allow_default_upstream,
+        &cfg.workspace_acl,
     )?;
     let target = format!("{}:{}", upstream_host, port);
+    cfg.circuit_breaker.check(&target)?;
     info!(client = %remote_addr, %target, "tcp tunnel via CONNECT");
+    let breaker = cfg.circuit_breaker.clone();
+    let limit = cfg.tunnel_byte_limit;
 
     // Consume request to get parts for upgrade later
     let (parts, _incoming) = req.into_parts();
@@ -1043,9 +2478,11 @@ async fn handle_connect(
         let original_req = Request::from_parts(parts, ());
         match hyper::upgrade::on(original_req).await {
             Ok(upgraded) => {
-                let mut client_io = TokioIo::new(upgraded);
+                let mut client_io = CappedIo::new(TokioIo::new(upgraded), limit);
                 match TcpStream::connect(&target).await {
-                    Ok(mut upstream) => {
+                    Ok(upstream) => {
+                        breaker.record_success(&target);
+                        let mut upstream = CappedIo::new(upstream, limit);
                         if let Err(e) = copy_bidirectional(&mut client_io, &mut upstream).await {
                             warn!(%e, "tcp tunnel error");
                         }
@@ -1054,6 +2491,7 @@ async fn handle_connect(
                     }
                     Err(e) => {
                         warn!(%e, "failed to connect to upstream for CONNECT");
+                        breaker.record_failure(&target);
                         let _ = client_io
                             .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
                             .await;
@@ -1,7 +1,8 @@
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
 
 use clap::Parser;
-use tracing::info;
+use tracing::{error, info};
 
 #[derive(Parser, Debug, Clone)]
 #[command(
@@ -23,6 +24,98 @@ struct Args {
     /// Allow requests without workspace headers to route to the default upstream host.
     #[arg(long, env = "CMUX_ALLOW_DEFAULT_UPSTREAM", default_value_t = true)]
     allow_default_upstream: bool,
+
+    /// Enable path-based routing under this prefix, e.g. `/_cmux` routes
+    /// `/_cmux/3000/api` to port 3000 with path `/api`.
+    #[arg(long, env = "CMUX_PATH_ROUTING_PREFIX")]
+    path_routing_prefix: Option<String>,
+
+    /// Forward the client's Host header unchanged instead of applying local-host handling.
+    #[arg(
+        long,
+        env = "CMUX_PRESERVE_CLIENT_HOST",
+        conflicts_with = "rewrite_host"
+    )]
+    preserve_client_host: bool,
+
+    /// Always send this Host header to upstreams.
+    #[arg(long, env = "CMUX_REWRITE_HOST")]
+    rewrite_host: Option<String>,
+
+    /// Only honor the --trusted-header headers from these client IPs (comma-separated).
+    /// Unset honors them from any client.
+    #[arg(long, env = "CMUX_TRUSTED_CLIENTS", value_delimiter = ',')]
+    trusted_clients: Option<Vec<IpAddr>>,
+
+    /// Internal headers stripped from clients outside --trusted-clients (comma-separated).
+    #[arg(
+        long = "trusted-header",
+        env = "CMUX_TRUSTED_HEADERS",
+        value_delimiter = ',',
+        default_values_t = cmux_proxy::DEFAULT_TRUSTED_HEADERS.iter().map(|name| name.to_string())
+    )]
+    trusted_headers: Vec<String>,
+
+    /// Close upgrade/CONNECT tunnels after this many bytes in either direction.
+    #[arg(long, env = "CMUX_TUNNEL_BYTE_LIMIT")]
+    tunnel_byte_limit: Option<u64>,
+
+    /// Only route to workspaces matching one of these patterns (comma-separated, `*` wildcard).
+    #[arg(long, env = "CMUX_WORKSPACE_ALLOW", value_delimiter = ',')]
+    workspace_allow: Option<Vec<String>>,
+
+    /// Never route to workspaces matching these patterns (comma-separated, `*` wildcard).
+    #[arg(long, env = "CMUX_WORKSPACE_DENY", value_delimiter = ',')]
+    workspace_deny: Vec<String>,
+
+    /// Path the proxy answers itself with a JSON health report (empty to disable).
+    #[arg(long, env = "CMUX_HEALTH_PATH", default_value = cmux_proxy::DEFAULT_HEALTH_PATH)]
+    health_path: String,
+
+    /// Expect a PROXY protocol v1/v2 header on every client connection (behind a TCP load balancer).
+    #[arg(long, env = "CMUX_PROXY_PROTOCOL")]
+    proxy_protocol: bool,
+
+    /// Cache immutable static asset responses in memory, up to this many bytes.
+    #[arg(long, env = "CMUX_ASSET_CACHE_BYTES")]
+    asset_cache_bytes: Option<usize>,
+
+    /// Serve on sockets passed by systemd socket activation (LISTEN_FDS) instead of
+    /// binding --listen addresses.
+    #[arg(long, env = "CMUX_SOCKET_ACTIVATION")]
+    socket_activation: bool,
+
+    /// Default time (ms) to wait for upstream response headers on HTTP requests.
+    #[arg(long, env = "CMUX_REQUEST_TIMEOUT_MS")]
+    request_timeout_ms: Option<u64>,
+
+    /// Let X-Cmux-Timeout-Ms pick the timeout per request (forwarded as X-Request-Timeout-Ms).
+    #[arg(long, env = "CMUX_HONOR_TIMEOUT_HEADER")]
+    honor_timeout_header: bool,
+
+    /// Upper bound (ms) for timeouts requested via X-Cmux-Timeout-Ms.
+    #[arg(long, env = "CMUX_MAX_REQUEST_TIMEOUT_MS", default_value_t = 300_000)]
+    max_request_timeout_ms: u64,
+
+    /// Header to set on every proxied request, as NAME=VALUE (repeatable). Client-sent
+    /// copies of the same header are dropped.
+    #[arg(long = "inject-header", env = "CMUX_INJECT_HEADERS", value_parser = parse_header_pair)]
+    inject_headers: Vec<(http::HeaderName, http::HeaderValue)>,
+
+    /// Largest client request head (request line plus headers) in bytes; larger ones get 431.
+    #[arg(long, env = "CMUX_MAX_REQUEST_HEADER_BYTES", default_value_t = cmux_proxy::DEFAULT_MAX_REQUEST_HEADER_BYTES)]
+    max_request_header_bytes: usize,
+}
+
+fn parse_header_pair(s: &str) -> Result<(http::HeaderName, http::HeaderValue), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{}`", s))?;
+    let header_name = http::HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name `{}`: {}", name, e))?;
+    let header_value = http::HeaderValue::from_str(value)
+        .map_err(|e| format!("invalid value for header `{}`: {}", name, e))?;
+    Ok((header_name, header_value))
 }
 
 #[tokio::main]
@@ -42,6 +135,23 @@ async fn main() {
         "listen" = ?args.listen,
         "upstream_host" = %args.upstream_host,
         allow_default_upstream = args.allow_default_upstream,
+        "path_routing_prefix" = ?args.path_routing_prefix,
+        "rewrite_host" = ?args.rewrite_host,
+        preserve_client_host = args.preserve_client_host,
+        "trusted_clients" = ?args.trusted_clients,
+        "trusted_headers" = ?args.trusted_headers,
+        "tunnel_byte_limit" = ?args.tunnel_byte_limit,
+        "workspace_allow" = ?args.workspace_allow,
+        "workspace_deny" = ?args.workspace_deny,
+        "health_path" = %args.health_path,
+        proxy_protocol = args.proxy_protocol,
+        "asset_cache_bytes" = ?args.asset_cache_bytes,
+        socket_activation = args.socket_activation,
+        "request_timeout_ms" = ?args.request_timeout_ms,
+        honor_timeout_header = args.honor_timeout_header,
+        max_request_timeout_ms = args.max_request_timeout_ms,
+        "inject_headers" = ?args.inject_headers.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        max_request_header_bytes = args.max_request_header_bytes,
         "Starting cmux-proxy"
     );
 
@@ -55,18 +165,69 @@ async fn main() {
     listens.dedup();
     let listens = dedupe_wildcard_v4(listens);
 
-    let upstream_host = args.upstream_host;
-    let allow_default_upstream = args.allow_default_upstream;
+    let host_policy = match args.rewrite_host {
+        Some(host) => cmux_proxy::HostPolicy::RewriteTo(host),
+        None if args.preserve_client_host => cmux_proxy::HostPolicy::PreserveClient,
+        None => cmux_proxy::HostPolicy::ForceLocal,
+    };
 
-    let (bound, handle) =
-        cmux_proxy::spawn_proxy_multi(listens, upstream_host, allow_default_upstream, async {
-            let _ = tokio::signal::ctrl_c().await;
-        });
+    let cfg = cmux_proxy::ProxyConfig {
+        upstream_host: args.upstream_host,
+        allow_default_upstream: args.allow_default_upstream,
+        path_routing_prefix: args.path_routing_prefix,
+        host_policy,
+        trusted_clients: args.trusted_clients,
+        trusted_headers: args.trusted_headers,
+        tunnel_byte_limit: args.tunnel_byte_limit,
+        workspace_acl: cmux_proxy::WorkspaceAcl {
+            allow: args.workspace_allow,
+            deny: args.workspace_deny,
+        },
+        health_path: (!args.health_path.is_empty()).then_some(args.health_path),
+        expect_proxy_protocol: args.proxy_protocol,
+        asset_cache: args.asset_cache_bytes.map(|max_bytes| {
+            let mut cache = cmux_proxy::AssetCache::default();
+            cache.max_bytes = max_bytes;
+            cache
+        }),
+        request_timeout: cmux_proxy::RequestTimeout {
+            default: args.request_timeout_ms.map(Duration::from_millis),
+            honor_header: args.honor_timeout_header,
+            max: Duration::from_millis(args.max_request_timeout_ms),
+        },
+        inject_headers: args.inject_headers,
+        max_request_header_bytes: args.max_request_header_bytes,
+        ..Default::default()
+    };
+
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    let (bound, handle) = if args.socket_activation {
+        let listeners = systemd_listeners();
+        if listeners.is_empty() {
+            error!("--socket-activation set but no sockets were passed (LISTEN_FDS)");
+            std::process::exit(1);
+        }
+        cmux_proxy::spawn_proxy_on_listeners(listeners, cfg, shutdown)
+    } else {
+        cmux_proxy::spawn_proxy_multi_with_config(listens, cfg, shutdown)
+    };
     info!("bound_addrs" = ?bound, "proxy started");
     let _ = handle.await;
 }
 // server logic moved to library
 
+#[cfg(unix)]
+fn systemd_listeners() -> Vec<std::net::TcpListener> {
+    cmux_proxy::listeners_from_systemd()
+}
+
+#[cfg(not(unix))]
+fn systemd_listeners() -> Vec<std::net::TcpListener> {
+    Vec::new()
+}
+
 fn dedupe_wildcard_v4(listens: Vec<SocketAddr>) -> Vec<SocketAddr> {
     let mut result = Vec::new();
     for addr in listens.into_iter() {
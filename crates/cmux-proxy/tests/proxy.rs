@@ -1,6 +1,8 @@
 use std::convert::Infallible;
 use std::io::ErrorKind;
 use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
 
 use bytes::Bytes;
@@ -106,6 +108,10 @@ async fn start_upstream_http() -> SocketAddr {
     let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
         .await
         .unwrap();
+    serve_upstream_http(listener)
+}
+
+fn serve_upstream_http(listener: TcpListener) -> SocketAddr {
     let local = listener.local_addr().unwrap();
     tokio::spawn(async move {
         loop {
@@ -272,7 +278,14 @@ async fn start_proxy(
         listen,
         upstream_host: upstream_host.to_string(),
         allow_default_upstream,
+        ..Default::default()
     };
+    start_proxy_with_config(cfg).await
+}
+
+async fn start_proxy_with_config(
+    cfg: ProxyConfig,
+) -> (SocketAddr, oneshot::Sender<()>, tokio::task::JoinHandle<()>) {
     let (tx, rx) = oneshot::channel::<()>();
     let (bound, handle) = cmux_proxy::spawn_proxy(
         cfg,
@@ -296,7 +309,7 @@ async fn test_http_proxy_routes_by_header() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -365,7 +378,7 @@ async fn test_wildcard_bind_accepts_localhost_clients() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -395,7 +408,7 @@ async fn test_websocket_proxy_upgrade() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -444,7 +457,7 @@ async fn test_connect_tcp_tunnel() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -501,7 +514,7 @@ async fn test_websocket_end_to_end_frames() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -559,7 +572,7 @@ async fn test_websocket_ping_pong_forwarding() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -601,7 +614,7 @@ async fn test_concurrent_websocket_connections() {
     let (proxy_addr, shutdown, handle) = start_proxy(
         SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
         "127.0.0.1",
-        false,
+        true,
     )
     .await;
 
@@ -703,6 +716,47 @@ async fn test_http2_clients_are_supported() {
     let _ = handle.await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http2_authority_port_is_not_absolute_form() {
+    let upstream_addr = start_upstream_http().await;
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+
+    let stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let (mut send_request, connection) = http2::Builder::new(TokioExecutor::new())
+        .handshake(TokioIo::new(stream))
+        .await
+        .expect("http2 handshake");
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    // The :authority port differs from the routing header; HTTP/1.1 would reject that as
+    // ambiguous, but for h2 only the header routes
+    let req = Request::builder()
+        .method("GET")
+        .uri("http://cmux-http2.test:1/hello")
+        .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+
+    let resp = send_request
+        .send_request(req)
+        .await
+        .expect("http2 response");
+    let status = resp.status();
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(status, StatusCode::OK, "body {:?}", body);
+    assert_eq!(&body[..], b"ok:GET:/hello");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
 async fn test_host_override_header_sets_host() {
     let upstream_addr = start_upstream_host_echo().await;
@@ -772,3 +826,1091 @@ async fn test_missing_host_override_keeps_host_header() {
     let _ = shutdown.send(());
     let _ = handle.await;
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_path_prefix_routing_strips_prefix() {
+    let upstream_addr = start_upstream_http().await;
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        path_routing_prefix: Some("/_cmux".to_string()),
+        ..Default::default()
+    })
+    .await;
+
+    let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+    let url = format!(
+        "http://{}:{}/_cmux/{}/foo/bar",
+        proxy_addr.ip(),
+        proxy_addr.port(),
+        upstream_addr.port()
+    );
+    let req = Request::builder()
+        .method("GET")
+        .uri(url)
+        .body(Empty::new())
+        .unwrap();
+    let resp = timeout(Duration::from_secs(5), client.request(req))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    let s = String::from_utf8(body.to_vec()).unwrap();
+    assert_eq!(s, "ok:GET:/foo/bar");
+
+    // Prefix with a non-numeric port segment is rejected
+    let url_bad = format!(
+        "http://{}:{}/_cmux/notaport",
+        proxy_addr.ip(),
+        proxy_addr.port()
+    );
+    let req_bad = Request::builder()
+        .method("GET")
+        .uri(url_bad)
+        .body(Empty::new())
+        .unwrap();
+    let resp_bad = timeout(Duration::from_secs(5), client.request(req_bad))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp_bad.status(), StatusCode::BAD_REQUEST);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_upstream_connection_refused_is_categorized() {
+    // Reserve a port and release it so nothing is listening there
+    let closed_port = std::net::TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+
+    let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+    let url = format!("http://{}:{}/down", proxy_addr.ip(), proxy_addr.port());
+    let req = Request::builder()
+        .method("GET")
+        .uri(url)
+        .header("X-Cmux-Port-Internal", closed_port.to_string())
+        .body(Empty::new())
+        .unwrap();
+    let resp = timeout(Duration::from_secs(10), client.request(req))
+        .await
+        .expect("resp timeout")
+        .unwrap();
+    assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    assert_eq!(
+        resp.headers().get("x-cmux-upstream-error").unwrap(),
+        "connection_refused"
+    );
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    let s = String::from_utf8(body.to_vec()).unwrap();
+    assert!(
+        s.starts_with("connection_refused:"),
+        "unexpected body: {}",
+        s
+    );
+    assert!(!s.contains("tcp connect error"), "raw error leaked: {}", s);
+    assert!(!s.contains("os error"), "raw error leaked: {}", s);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_response_bytes_are_accounted_per_workspace() {
+    let upstream_addr = start_upstream_http().await;
+    let counters = cmux_proxy::ByteCounters::default();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        byte_counters: counters.clone(),
+        ..Default::default()
+    })
+    .await;
+
+    let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+    let mut total = 0u64;
+    for path in ["/a", "/counted/bytes"] {
+        let url = format!("http://{}:{}{}", proxy_addr.ip(), proxy_addr.port(), path);
+        let req = Request::builder()
+            .method("GET")
+            .uri(url)
+            .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+            .body(Empty::new())
+            .unwrap();
+        let resp = timeout(Duration::from_secs(5), client.request(req))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        total += body.len() as u64;
+    }
+
+    assert_eq!(
+        total,
+        ("ok:GET:/a".len() + "ok:GET:/counted/bytes".len()) as u64
+    );
+    assert_eq!(
+        counters.get(cmux_proxy::ByteCounters::DEFAULT_WORKSPACE),
+        total
+    );
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+/// Upstream serving `/immutable.js` as a long-lived asset and `/fresh.js` as `no-store`,
+/// counting the requests it receives.
+async fn start_upstream_assets() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let local = listener.local_addr().unwrap();
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+                    async move {
+                        let cache_control = if req.uri().path() == "/immutable.js" {
+                            "public, max-age=31536000, immutable"
+                        } else {
+                            "no-store"
+                        };
+                        let resp = Response::builder()
+                            .header("cache-control", cache_control)
+                            .body(Full::new(Bytes::from(format!("asset#{n}"))))
+                            .unwrap();
+                        Ok::<_, Infallible>(resp)
+                    }
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    (local, hits)
+}
+
+async fn raw_http_request(proxy_addr: SocketAddr, req: String) -> String {
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let mut buf = Vec::new();
+    timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("read timeout")
+        .unwrap();
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_absolute_form_request_target() {
+    let upstream_addr = start_upstream_http().await;
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+    let port = upstream_addr.port();
+
+    // The authority's port selects the upstream and only the path is forwarded
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET http://127.0.0.1:{port}/abs HTTP/1.1\r\nHost: ignored\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(resp.ends_with("ok:GET:/abs"), "resp: {resp}");
+
+    // A matching internal header is accepted
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET http://127.0.0.1:{port}/abs HTTP/1.1\r\nHost: ignored\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+
+    // A conflicting internal header is rejected
+    let other = if port == 1 { 2 } else { port - 1 };
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET http://127.0.0.1:{port}/abs HTTP/1.1\r\nHost: ignored\r\nX-Cmux-Port-Internal: {other}\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 400"), "resp: {resp}");
+    assert!(
+        resp.contains("conflicts with X-Cmux-Port-Internal"),
+        "resp: {resp}"
+    );
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_host_policy_controls_upstream_host() {
+    use cmux_proxy::HostPolicy;
+
+    let upstream_addr = start_upstream_host_echo().await;
+    let cases = [
+        (HostPolicy::ForceLocal, "localhost:3006"),
+        (HostPolicy::PreserveClient, "vhost.example.test"),
+        (
+            HostPolicy::RewriteTo("backend.internal".to_string()),
+            "backend.internal",
+        ),
+    ];
+    for (policy, expected) in cases {
+        let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+            listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            host_policy: policy.clone(),
+            ..Default::default()
+        })
+        .await;
+
+        let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+        let url = format!("http://{}:{}/policy", proxy_addr.ip(), proxy_addr.port());
+        let req = Request::builder()
+            .method("GET")
+            .uri(url)
+            .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+            .header("Host", "vhost.example.test")
+            .header("X-Cmux-Host-Override", "localhost:3006")
+            .body(Empty::new())
+            .unwrap();
+        let resp = timeout(Duration::from_secs(5), client.request(req))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected, "policy {:?}", policy);
+
+        let _ = shutdown.send(());
+        let _ = handle.await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_circuit_breaker_trips_and_recovers() {
+    let closed_port = std::net::TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        circuit_breaker: cmux_proxy::CircuitBreaker::new(
+            2,
+            Duration::from_secs(30),
+            Duration::from_millis(500),
+        ),
+        ..Default::default()
+    })
+    .await;
+
+    let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+    let send = || {
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("http://{}/cb", proxy_addr))
+            .header("X-Cmux-Port-Internal", closed_port.to_string())
+            .body(Empty::new())
+            .unwrap();
+        timeout(Duration::from_secs(10), client.request(req))
+    };
+
+    for _ in 0..2 {
+        let resp = send().await.expect("resp timeout").unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    // Tripped: rejected without attempting to connect
+    let resp = send().await.expect("resp timeout").unwrap();
+    assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        resp.headers().get("x-cmux-upstream-error").unwrap(),
+        "circuit_open"
+    );
+    assert!(resp.headers().contains_key("retry-after"));
+
+    // Bring the upstream up; once the cooldown passes the probe succeeds
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, closed_port)))
+        .await
+        .unwrap();
+    serve_upstream_http(listener);
+    sleep(Duration::from_millis(600)).await;
+    let resp = send().await.expect("resp timeout").unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let resp = send().await.expect("resp timeout").unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_host_override_requires_trusted_source() {
+    let upstream_addr = start_upstream_host_echo().await;
+    let cases = [
+        (vec![Ipv4Addr::LOCALHOST.into()], "localhost:3006"),
+        (
+            vec![Ipv4Addr::new(192, 0, 2, 1).into()],
+            "client.example.test",
+        ),
+    ];
+    for (allowlist, expected) in cases {
+        let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+            listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            trusted_clients: Some(allowlist),
+            ..Default::default()
+        })
+        .await;
+
+        let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+        let req = Request::builder()
+            .method("GET")
+            .uri(format!("http://{}/trust", proxy_addr))
+            .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+            .header("Host", "client.example.test")
+            .header("X-Cmux-Host-Override", "localhost:3006")
+            .body(Empty::new())
+            .unwrap();
+        let resp = timeout(Duration::from_secs(5), client.request(req))
+            .await
+            .expect("resp timeout")
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, expected);
+
+        let _ = shutdown.send(());
+        let _ = handle.await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_tunnel_byte_limit_tears_down_tunnel() {
+    let (echo_addr, _echo_handle) = start_upstream_tcp_echo().await;
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        tunnel_byte_limit: Some(16),
+        ..Default::default()
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let req = format!(
+        "CONNECT foo HTTP/1.1\r\nHost: foo\r\nX-Cmux-Port-Internal: {}\r\n\r\n",
+        echo_addr.port()
+    );
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let mut resp_buf = Vec::new();
+    let mut tmp = [0u8; 1024];
+    while !resp_buf.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = timeout(Duration::from_secs(5), stream.read(&mut tmp))
+            .await
+            .expect("read timeout")
+            .unwrap();
+        assert!(n > 0);
+        resp_buf.extend_from_slice(&tmp[..n]);
+    }
+    assert!(resp_buf.starts_with(b"HTTP/1.1 200"));
+
+    // Under the limit the tunnel works normally
+    let payload = b"ping-123";
+    stream.write_all(payload).await.unwrap();
+    let mut recv = vec![0u8; payload.len()];
+    timeout(Duration::from_secs(5), stream.read_exact(&mut recv))
+        .await
+        .expect("echo timeout")
+        .unwrap();
+    assert_eq!(&recv, payload);
+
+    // Going over it closes the tunnel instead of forwarding
+    stream.write_all(b"0123456789abcdef").await.unwrap();
+    let mut rest = Vec::new();
+    let _ = timeout(Duration::from_secs(5), stream.read_to_end(&mut rest))
+        .await
+        .expect("tunnel was not torn down");
+    assert!(rest.is_empty(), "unexpected data: {:?}", rest);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_proxy_handle_ready_and_shutdown() {
+    let upstream_addr = start_upstream_http().await;
+    let handle = cmux_proxy::start_proxy(
+        vec![
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        ],
+        ProxyConfig::default(),
+    );
+    timeout(Duration::from_secs(5), handle.ready())
+        .await
+        .expect("ready timeout");
+    assert_eq!(handle.addrs().len(), 2);
+
+    // No settle delay: every listener is accepting once ready resolves
+    for addr in handle.addrs() {
+        let resp = raw_http_request(
+            *addr,
+            format!(
+                "GET /ready HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {}\r\nConnection: close\r\n\r\n",
+                upstream_addr.port()
+            ),
+        )
+        .await;
+        assert!(
+            resp.starts_with("HTTP/1.1 200"),
+            "unexpected response: {resp}"
+        );
+        assert!(
+            resp.contains("ok:GET:/ready"),
+            "unexpected response: {resp}"
+        );
+    }
+
+    let addr = handle.addrs()[0];
+    timeout(Duration::from_secs(5), handle.shutdown())
+        .await
+        .expect("shutdown timeout");
+    assert!(TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_asset_cache_serves_immutable_assets() {
+    let (upstream_addr, upstream_hits) = start_upstream_assets().await;
+    let cache = cmux_proxy::AssetCache::default();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        asset_cache: Some(cache.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client: Client<HttpConnector, TestRequestBody> = new_test_client();
+    let fetch = |path: &str| {
+        let req = Request::builder()
+            .uri(format!("http://{}{}", proxy_addr, path))
+            .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+            .body(Empty::new())
+            .unwrap();
+        let resp = client.request(req);
+        async move {
+            let resp = timeout(Duration::from_secs(5), resp)
+                .await
+                .expect("resp timeout")
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let cache_control = resp.headers()["cache-control"].clone();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            (cache_control, body)
+        }
+    };
+
+    // The second request for the immutable asset is answered from the cache
+    let first = fetch("/immutable.js").await;
+    let second = fetch("/immutable.js").await;
+    assert_eq!(first, second);
+    assert_eq!(first.1, "asset#1");
+    assert_eq!(upstream_hits.load(Ordering::SeqCst), 1);
+    assert_eq!(cache.len(), 1);
+
+    // no-store responses always go upstream
+    assert_eq!(fetch("/fresh.js").await.1, "asset#2");
+    assert_eq!(fetch("/fresh.js").await.1, "asset#3");
+    assert_eq!(upstream_hits.load(Ordering::SeqCst), 3);
+    assert_eq!(cache.len(), 1);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_asset_cache_keys_on_upstream_host() {
+    let (upstream_addr, upstream_hits) = start_upstream_assets().await;
+    let port = upstream_addr.port();
+    let cache = cmux_proxy::AssetCache::default();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        asset_cache: Some(cache.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let fetch = |host: &str| {
+        raw_http_request(
+            proxy_addr,
+            format!(
+                "GET /immutable.js HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nX-Cmux-Host-Override: {host}\r\nConnection: close\r\n\r\n"
+            ),
+        )
+    };
+
+    // Same path on the same upstream port, but a different virtual host each time
+    assert!(fetch("a.test").await.ends_with("asset#1"));
+    assert!(fetch("b.test").await.ends_with("asset#2"));
+    assert!(fetch("a.test").await.ends_with("asset#1"));
+    assert_eq!(upstream_hits.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.len(), 2);
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[cfg(unix)]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_proxy_serves_on_prebound_listener_fd() {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    // Stand-in for a socket systemd bound and passed to us by descriptor
+    let listener = std::net::TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fd = listener.into_raw_fd();
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (bound, handle) =
+        cmux_proxy::spawn_proxy_on_listeners(vec![listener], ProxyConfig::default(), async move {
+            let _ = shutdown_rx.await;
+        });
+    assert_eq!(bound, vec![addr]);
+
+    let resp = raw_http_request(
+        addr,
+        "GET /__cmux_health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string(),
+    )
+    .await;
+    assert!(
+        resp.starts_with("HTTP/1.1 200"),
+        "unexpected response: {resp}"
+    );
+
+    let _ = shutdown_tx.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_proxy_protocol_header_is_required_when_expected() {
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        expect_proxy_protocol: true,
+        ..Default::default()
+    })
+    .await;
+
+    let resp = raw_http_request(
+        proxy_addr,
+        "PROXY TCP4 203.0.113.7 127.0.0.1 51234 80\r\nGET /__cmux_health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string(),
+    )
+    .await;
+    assert!(
+        resp.starts_with("HTTP/1.1 200"),
+        "unexpected response: {resp}"
+    );
+
+    // Without the header the connection is dropped unanswered (possibly with a reset)
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(b"GET /__cmux_health HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    let read = timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("read timeout");
+    assert!(
+        read.is_err() || buf.is_empty(),
+        "unexpected response: {buf:?}"
+    );
+
+    // A client that never sends the header is dropped rather than held open
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let mut buf = Vec::new();
+    let read = timeout(Duration::from_secs(10), stream.read_to_end(&mut buf))
+        .await
+        .expect("idle connection was not closed");
+    assert!(
+        read.is_err() || buf.is_empty(),
+        "unexpected response: {buf:?}"
+    );
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_health_path_needs_no_routing_headers() {
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+
+    let resp = raw_http_request(
+        proxy_addr,
+        "GET /__cmux_health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string(),
+    )
+    .await;
+    assert!(
+        resp.starts_with("HTTP/1.1 200"),
+        "unexpected response: {resp}"
+    );
+    assert!(
+        resp.contains("application/json"),
+        "unexpected response: {resp}"
+    );
+    assert!(
+        resp.contains(r#""status":"ok""#),
+        "unexpected response: {resp}"
+    );
+    assert!(
+        resp.contains(r#""uptime_secs":"#),
+        "unexpected response: {resp}"
+    );
+
+    // Any other path still needs the port header
+    let resp = raw_http_request(
+        proxy_addr,
+        "GET /__cmux_health/x HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_string(),
+    )
+    .await;
+    assert!(
+        resp.starts_with("HTTP/1.1 400"),
+        "unexpected response: {resp}"
+    );
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+/// Upstream that sleeps for `?ms` milliseconds on `/slow` and otherwise echoes the timeout
+/// headers it received.
+async fn start_upstream_deadline_echo() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let local = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    if req.uri().path() == "/slow" {
+                        sleep(Duration::from_secs(2)).await;
+                    }
+                    let header = |name: &str| {
+                        req.headers()
+                            .get(name)
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or("-")
+                            .to_string()
+                    };
+                    let body = format!(
+                        "internal={} forwarded={}",
+                        header("x-cmux-timeout-ms"),
+                        header("x-request-timeout-ms")
+                    );
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    local
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_timeout_header_sets_request_deadline() {
+    let upstream_addr = start_upstream_deadline_echo().await;
+    let port = upstream_addr.port();
+    let mut cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        ..Default::default()
+    };
+    cfg.request_timeout.honor_header = true;
+    cfg.request_timeout.max = Duration::from_secs(10);
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(cfg).await;
+
+    // The internal header is replaced by the forwarded deadline
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /fast HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nX-Cmux-Timeout-Ms: 1500\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(resp.ends_with("internal=- forwarded=1500"), "resp: {resp}");
+
+    // A slow upstream times out at the requested deadline, well before it answers
+    let started = std::time::Instant::now();
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /slow HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nX-Cmux-Timeout-Ms: 200\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    let elapsed = started.elapsed();
+    assert!(resp.starts_with("HTTP/1.1 504"), "resp: {resp}");
+    assert!(
+        resp.to_ascii_lowercase()
+            .contains("x-cmux-upstream-error: timeout"),
+        "resp: {resp}"
+    );
+    assert!(
+        elapsed >= Duration::from_millis(200) && elapsed < Duration::from_millis(1500),
+        "elapsed: {elapsed:?}"
+    );
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_timeouts_trip_the_circuit_breaker() {
+    let upstream_addr = start_upstream_deadline_echo().await;
+    let port = upstream_addr.port();
+    let mut cfg = ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        circuit_breaker: cmux_proxy::CircuitBreaker::new(
+            2,
+            Duration::from_secs(30),
+            Duration::from_secs(30),
+        ),
+        ..Default::default()
+    };
+    cfg.request_timeout.default = Some(Duration::from_millis(100));
+    let breaker = cfg.circuit_breaker.clone();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(cfg).await;
+
+    let request = format!(
+        "GET /slow HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n"
+    );
+    for _ in 0..2 {
+        let resp = raw_http_request(proxy_addr, request.clone()).await;
+        assert!(resp.starts_with("HTTP/1.1 504"), "resp: {resp}");
+    }
+    assert!(breaker.is_open(&format!("127.0.0.1:{port}")));
+    let resp = raw_http_request(proxy_addr, request).await;
+    assert!(resp.starts_with("HTTP/1.1 503"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_timeout_header_ignored_unless_enabled() {
+    let upstream_addr = start_upstream_deadline_echo().await;
+    let port = upstream_addr.port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        ..Default::default()
+    })
+    .await;
+
+    // Still stripped, but no deadline is applied or forwarded
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /fast HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nX-Cmux-Timeout-Ms: 1500\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.ends_with("internal=- forwarded=-"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+/// Upstream that answers with every value of the header named by the request path,
+/// joined with `,`.
+async fn start_upstream_header_echo() -> SocketAddr {
+    let listener = TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+        .await
+        .unwrap();
+    let local = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    let name = req.uri().path().trim_start_matches('/');
+                    let values: Vec<&str> = req
+                        .headers()
+                        .get_all(name)
+                        .iter()
+                        .filter_map(|v| v.to_str().ok())
+                        .collect();
+                    let body = values.join(",");
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+                });
+                let _ = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    local
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_injected_headers_replace_client_values() {
+    let upstream_addr = start_upstream_header_echo().await;
+    let port = upstream_addr.port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        inject_headers: vec![(
+            http::HeaderName::from_static("x-internal-token"),
+            http::HeaderValue::from_static("secret"),
+        )],
+        ..Default::default()
+    })
+    .await;
+
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /x-internal-token HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\nsecret"), "resp: {resp}");
+
+    // Spoofed copies (in any case) are dropped rather than forwarded alongside
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /x-internal-token HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nx-internal-token: spoofed\r\nX-INTERNAL-TOKEN: again\r\nConnection: close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.ends_with("\r\n\r\nsecret"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_injected_headers_survive_connection_nomination() {
+    let upstream_addr = start_upstream_header_echo().await;
+    let port = upstream_addr.port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        inject_headers: vec![(
+            http::HeaderName::from_static("x-internal-token"),
+            http::HeaderValue::from_static("secret"),
+        )],
+        ..Default::default()
+    })
+    .await;
+
+    // Naming the injected header as hop-by-hop must not delete the proxy's own copy
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /x-internal-token HTTP/1.1\r\nHost: localhost\r\nX-Cmux-Port-Internal: {port}\r\nConnection: X-Internal-Token, close\r\n\r\n"
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(resp.ends_with("\r\n\r\nsecret"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_http10_client_gets_no_keep_alive() {
+    let upstream_addr = start_upstream_header_echo().await;
+    let port = upstream_addr.port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        ..Default::default()
+    })
+    .await;
+
+    // raw_http_request reads to EOF, so returning at all means the proxy closed
+    let resp = raw_http_request(
+        proxy_addr,
+        format!("GET /x-hop HTTP/1.0\r\nHost: x\r\nX-Cmux-Port-Internal: {port}\r\nX-Hop: 1\r\nConnection: X-Hop\r\n\r\n"),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.0 200"), "resp: {resp}");
+    assert!(
+        !resp.to_ascii_lowercase().contains("keep-alive"),
+        "resp: {resp}"
+    );
+    // The header the client nominated as hop-by-hop never reached the upstream
+    assert!(resp.ends_with("\r\n\r\n"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_connection_close_request_closes_client_connection() {
+    let upstream_addr = start_upstream_http().await;
+    let port = upstream_addr.port();
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        ..Default::default()
+    })
+    .await;
+
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    stream
+        .write_all(
+            format!(
+                "GET /one HTTP/1.1\r\nHost: x\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n\
+                 GET /two HTTP/1.1\r\nHost: x\r\nX-Cmux-Port-Internal: {port}\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .unwrap();
+    let mut buf = Vec::new();
+    timeout(Duration::from_secs(5), stream.read_to_end(&mut buf))
+        .await
+        .expect("connection left open")
+        .unwrap();
+    let resp = String::from_utf8_lossy(&buf);
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(
+        resp.to_ascii_lowercase().contains("connection: close"),
+        "resp: {resp}"
+    );
+    // The pipelined second request is never answered
+    assert!(resp.ends_with("ok:GET:/one"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_missing_workspace_rejected_when_default_disallowed() {
+    let upstream_addr = start_upstream_http().await;
+    let port = upstream_addr.port();
+    let request = format!(
+        "GET /hi HTTP/1.1\r\nHost: example.test\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n"
+    );
+
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        false,
+    )
+    .await;
+    let resp = raw_http_request(proxy_addr, request.clone()).await;
+    assert!(resp.starts_with("HTTP/1.1 400"), "resp: {resp}");
+    assert!(resp.contains("missing workspace"), "resp: {resp}");
+    let _ = shutdown.send(());
+    let _ = handle.await;
+
+    let (proxy_addr, shutdown, handle) = start_proxy(
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        "127.0.0.1",
+        true,
+    )
+    .await;
+    let resp = raw_http_request(proxy_addr, request).await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+    assert!(resp.ends_with("ok:GET:/hi"), "resp: {resp}");
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_oversized_request_headers_get_431() {
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        max_request_header_bytes: 16 * 1024,
+        ..Default::default()
+    })
+    .await;
+
+    // The proxy stops reading mid-head, so the close may arrive as a reset after the 431
+    let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+    let req = format!(
+        "GET /__cmux_health HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\n\r\n",
+        "a".repeat(32 * 1024)
+    );
+    let _ = stream.write_all(req.as_bytes()).await;
+    let mut buf = vec![0u8; 1024];
+    let n = timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("read timeout")
+        .unwrap();
+    let resp = String::from_utf8_lossy(&buf[..n]);
+    assert!(resp.starts_with("HTTP/1.1 431"), "resp: {resp}");
+
+    // Heads within the limit still go through
+    let resp = raw_http_request(
+        proxy_addr,
+        format!(
+            "GET /__cmux_health HTTP/1.1\r\nHost: localhost\r\nX-Big: {}\r\nConnection: close\r\n\r\n",
+            "a".repeat(8 * 1024)
+        ),
+    )
+    .await;
+    assert!(resp.starts_with("HTTP/1.1 200"), "resp: {resp}");
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_trusted_headers_are_configurable() {
+    let upstream_addr = start_upstream_http().await;
+    let port = upstream_addr.port();
+    let request = format!(
+        "GET /gated HTTP/1.1\r\nHost: example.test\r\nX-Cmux-Port-Internal: {port}\r\nConnection: close\r\n\r\n"
+    );
+    let cases = [
+        (Ipv4Addr::LOCALHOST, "HTTP/1.1 200"),
+        (Ipv4Addr::new(192, 0, 2, 1), "HTTP/1.1 400"),
+    ];
+    for (trusted, expected) in cases {
+        let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+            listen: SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            trusted_clients: Some(vec![trusted.into()]),
+            trusted_headers: vec!["X-Cmux-Port-Internal".to_string()],
+            ..Default::default()
+        })
+        .await;
+
+        // From an untrusted peer the port header is dropped, leaving nothing to route on
+        let resp = raw_http_request(proxy_addr, request.clone()).await;
+        assert!(resp.starts_with(expected), "resp: {resp}");
+
+        let _ = shutdown.send(());
+        let _ = handle.await;
+    }
+}
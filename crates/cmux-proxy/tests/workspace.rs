@@ -5,7 +5,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::time::Duration;
 
 use bytes::Bytes;
-use cmux_proxy::{workspace_ip_from_name, ProxyConfig};
+use cmux_proxy::{workspace_ip_from_name, ProxyConfig, WorkspaceAcl};
 use futures_util::FutureExt;
 use http::{Method, Request, Response, StatusCode};
 use http_body_util::{BodyExt, Empty, Full};
@@ -85,7 +85,14 @@ async fn start_proxy(
         listen,
         upstream_host: upstream_host.to_string(),
         allow_default_upstream,
+        ..Default::default()
     };
+    start_proxy_with_config(cfg).await
+}
+
+async fn start_proxy_with_config(
+    cfg: ProxyConfig,
+) -> (SocketAddr, oneshot::Sender<()>, tokio::task::JoinHandle<()>) {
     let (tx, rx) = oneshot::channel::<()>();
     let (bound, handle) = cmux_proxy::spawn_proxy(
         cfg,
@@ -417,3 +424,48 @@ async fn test_same_port_isolation_across_workspaces() {
     let _ = shutdown.send(());
     let _ = handle.await;
 }
+
+#[cfg(target_os = "linux")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_workspace_acl_allows_and_denies_by_pattern() {
+    let allowed = "team-a-7";
+    let upstream_addr = start_upstream_http_on(workspace_ip_from_name(allowed).unwrap()).await;
+
+    let (proxy_addr, shutdown, handle) = start_proxy_with_config(ProxyConfig {
+        listen: SocketAddr::from((Ipv4Addr::LOCALHOST, next_port())),
+        allow_default_upstream: false,
+        workspace_acl: WorkspaceAcl {
+            allow: Some(vec!["team-a-*".to_string()]),
+            deny: vec!["*-admin-*".to_string()],
+        },
+        ..Default::default()
+    })
+    .await;
+
+    let client = new_test_client();
+    let send = |ws: &str| {
+        let req = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}/acl", proxy_addr))
+            .header("X-Cmux-Workspace-Internal", ws)
+            .header("X-Cmux-Port-Internal", upstream_addr.port().to_string())
+            .body(Empty::new())
+            .unwrap();
+        timeout(Duration::from_secs(5), client.request(req))
+    };
+
+    // Allowlisted workspace reaches the upstream on its derived IP
+    let resp = send(allowed).await.expect("resp timeout").unwrap();
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = resp.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"ok:GET:/acl");
+
+    // Denylisted (even though it matches the allowlist) and unlisted workspaces get 403
+    for ws in ["team-a-admin-7", "team-b-7"] {
+        let resp = send(ws).await.expect("resp timeout").unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN, "workspace {}", ws);
+    }
+
+    let _ = shutdown.send(());
+    let _ = handle.await;
+}
@@ -6,6 +6,7 @@ use std::io::Write;
 use std::time::Duration;
 
 use anyhow::{Context, Result};
+use cmux_terminal::{encode_key, Key, KeyModes, KeyMods};
 use crossterm::{
     event::{self, Event, KeyCode, KeyModifiers},
     terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
@@ -450,29 +451,29 @@ fn key_event_to_bytes(key: &event::KeyEvent) -> Vec<u8> {
         Backspace => vec![0x7f],
         Tab => vec![b'\t'],
         Esc => vec![0x1b],
-        Up => b"\x1b[A".to_vec(),
-        Down => b"\x1b[B".to_vec(),
-        Right => b"\x1b[C".to_vec(),
-        Left => b"\x1b[D".to_vec(),
-        Home => b"\x1b[H".to_vec(),
-        End => b"\x1b[F".to_vec(),
-        PageUp => b"\x1b[5~".to_vec(),
-        PageDown => b"\x1b[6~".to_vec(),
-        Delete => b"\x1b[3~".to_vec(),
-        Insert => b"\x1b[2~".to_vec(),
-        F(1) => b"\x1bOP".to_vec(),
-        F(2) => b"\x1bOQ".to_vec(),
-        F(3) => b"\x1bOR".to_vec(),
-        F(4) => b"\x1bOS".to_vec(),
-        F(5) => b"\x1b[15~".to_vec(),
-        F(6) => b"\x1b[17~".to_vec(),
-        F(7) => b"\x1b[18~".to_vec(),
-        F(8) => b"\x1b[19~".to_vec(),
-        F(9) => b"\x1b[20~".to_vec(),
-        F(10) => b"\x1b[21~".to_vec(),
-        F(11) => b"\x1b[23~".to_vec(),
-        F(12) => b"\x1b[24~".to_vec(),
-        _ => vec![],
+        code => {
+            let special = match code {
+                Up => Key::Up,
+                Down => Key::Down,
+                Right => Key::Right,
+                Left => Key::Left,
+                Home => Key::Home,
+                End => Key::End,
+                PageUp => Key::PageUp,
+                PageDown => Key::PageDown,
+                Delete => Key::Delete,
+                Insert => Key::Insert,
+                F(n) => Key::F(n),
+                _ => return vec![],
+            };
+            let mods = KeyMods {
+                shift: key.modifiers.contains(KeyModifiers::SHIFT),
+                alt: key.modifiers.contains(KeyModifiers::ALT),
+                ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+            };
+            // The remote application's cursor key mode isn't tracked on this side
+            encode_key(special, mods, KeyModes::default())
+        }
     }
 }
 
@@ -8,20 +8,22 @@
 mod cli;
 
 // Re-export terminal emulation library
-use cmux_terminal::{DaFilter, VirtualTerminal};
+use cmux_terminal::{
+    CursorStyle, DaFilter, FilterStats, VirtualTerminal, DEFAULT_MAX_GRID_DIMENSION,
+};
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     env,
     io::{Read, Write as IoWrite},
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result};
 use axum::{
     extract::{
-        ws::{Message, WebSocket},
+        ws::{CloseFrame, Message, WebSocket},
         Path, Query, State, WebSocketUpgrade,
     },
     http::StatusCode,
@@ -29,6 +31,7 @@ use axum::{
     routing::{delete, get, patch, post},
     Router,
 };
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use clap::{Parser, Subcommand};
 use futures::{SinkExt, StreamExt};
 use parking_lot::{Mutex, RwLock};
@@ -72,6 +75,41 @@ enum Commands {
         /// Port to listen on
         #[arg(short, long, env = "PTY_SERVER_PORT", default_value = "39383")]
         port: u16,
+
+        /// Seconds of inactivity before an event socket gets a heartbeat (0 = off)
+        #[arg(long, env = "PTY_HEARTBEAT_SECS", default_value = "30")]
+        heartbeat_secs: u64,
+
+        /// Drop output chunks identical to the previous one if it was sent less than
+        /// this many milliseconds ago (0 = off). Only chunks that repaint from an
+        /// absolute position (CUP, or CR and erase line) without a line feed are dropped
+        #[arg(long, env = "PTY_DEDUP_OUTPUT_MS", default_value = "0")]
+        dedup_output_ms: u64,
+
+        /// Output frames a slow terminal socket may fall behind before its backlog is
+        /// replaced with a full resync
+        #[arg(long, env = "PTY_SUBSCRIBER_QUEUE_FRAMES", default_value = "256")]
+        subscriber_queue_frames: usize,
+
+        /// Largest WebSocket frame payload sent to terminal sockets; bigger output
+        /// (such as a scrollback replay) is split across consecutive messages
+        #[arg(long, env = "PTY_MAX_WS_FRAME", default_value = "1048576")]
+        max_ws_frame: usize,
+
+        /// Enable endpoints for driving sessions' terminal emulators directly in tests
+        /// (POST /sessions/:id/process). Never enable in production.
+        #[arg(long, env = "PTY_ENABLE_TEST_ENDPOINTS")]
+        enable_test_endpoints: bool,
+
+        /// Report session health: after this many seconds without output a session is
+        /// "idle", or "hung" if its foreground process is in uninterruptible sleep (0 = off)
+        #[arg(
+            long = "detect-hung",
+            env = "PTY_DETECT_HUNG_SECS",
+            value_name = "SECS",
+            default_value = "0"
+        )]
+        detect_hung_secs: u64,
     },
 
     /// List all sessions
@@ -152,6 +190,8 @@ enum Commands {
 const INDEX_HTML: &str = include_str!("../static/index.html");
 const MAX_SCROLLBACK: usize = 100_000;
 const PTY_READ_BUFFER_SIZE: usize = 4096;
+const DEFAULT_SUBSCRIBER_QUEUE_LIMIT: usize = 256;
+const DEFAULT_MAX_WS_FRAME_BYTES: usize = 1024 * 1024;
 const PTY_WRITE_CHUNK_SIZE: usize = 512; // Small chunks for smooth writes
 const PTY_INPUT_CHANNEL_SIZE: usize = 1024; // Bounded channel for backpressure
 
@@ -166,6 +206,9 @@ enum ServerError {
 
     #[error("Failed to spawn PTY: {0}")]
     PtySpawnError(String),
+
+    #[error("Invalid session request: {0}")]
+    InvalidRequest(String),
 }
 
 impl IntoResponse for ServerError {
@@ -173,6 +216,7 @@ impl IntoResponse for ServerError {
         let (status, message) = match &self {
             ServerError::SessionNotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
             ServerError::PtySpawnError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
+            ServerError::InvalidRequest(_) => (StatusCode::BAD_REQUEST, self.to_string()),
         };
 
         let body = serde_json::json!({ "error": message });
@@ -201,6 +245,9 @@ struct CreateSessionRequest {
     /// Flexible metadata - clients can store any JSON here.
     /// Example: {"location": "editor", "type": "agent", "managed": true}
     metadata: Option<serde_json::Value>,
+    /// Keep the session (scrollback and terminal state) after the process exits,
+    /// reported with `alive: false` until it is explicitly deleted.
+    linger_after_exit: bool,
 }
 
 fn default_shell() -> String {
@@ -325,6 +372,32 @@ fn validate_cwd(cwd: &str) -> Result<String, &'static str> {
     Ok(path_str.to_string())
 }
 
+/// Check that the shell exists and is executable and that the cwd is an existing directory.
+fn check_spawn_target(shell: &str, cwd: &str) -> Result<(), String> {
+    match std::fs::metadata(shell) {
+        Ok(meta) if is_executable(&meta) => {}
+        Ok(_) => return Err(format!("shell is not executable: {}", shell)),
+        Err(_) => return Err(format!("shell not found: {}", shell)),
+    }
+    match std::fs::metadata(cwd) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(format!("cwd is not a directory: {}", cwd)),
+        Err(_) => Err(format!("cwd does not exist: {}", cwd)),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    meta.is_file() && meta.permissions().mode() & 0o111 != 0
+}
+
+/// Without unix permission bits, an existing file is taken to be runnable.
+#[cfg(not(unix))]
+fn is_executable(meta: &std::fs::Metadata) -> bool {
+    meta.is_file()
+}
+
 impl Default for CreateSessionRequest {
     fn default() -> Self {
         Self {
@@ -336,6 +409,7 @@ impl Default for CreateSessionRequest {
             name: None,
             client_id: None,
             metadata: None,
+            linger_after_exit: false,
         }
     }
 }
@@ -363,6 +437,34 @@ struct SessionInfo {
     /// Flexible metadata for client use (location, type, managed flag, etc.)
     #[serde(skip_serializing_if = "Option::is_none")]
     metadata: Option<serde_json::Value>,
+    /// Liveness heuristic, only reported when the server runs with `--detect-hung`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health: Option<SessionHealth>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SessionHealth {
+    /// Produced output recently
+    Active,
+    /// Quiet, but not stuck as far as the kernel shows
+    Idle,
+    /// Quiet with the foreground process in uninterruptible sleep
+    Hung,
+}
+
+/// Scheduler state letter of a process from `/proc/<pid>/stat` (`R`, `S`, `D`, ...), or
+/// None where `/proc` is unavailable or the process is gone.
+fn process_state(pid: u32) -> Option<char> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_proc_stat_state(&stat)
+}
+
+/// The state field follows the parenthesised command name, which may itself contain
+/// spaces and parentheses, so split after the last `)`.
+fn parse_proc_stat_state(stat: &str) -> Option<char> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.trim_start().chars().next()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -394,6 +496,14 @@ enum ServerEvent {
 
     #[serde(rename = "error")]
     Error { error: String },
+
+    /// Sent on idle event sockets so clients and proxies can tell idle from stalled
+    #[serde(rename = "heartbeat")]
+    Heartbeat,
+
+    /// Input written to a session whose metadata sets `"broadcast_input": true`
+    #[serde(rename = "input")]
+    Input { pty_id: String, data: String },
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -411,6 +521,7 @@ enum ClientMessage {
         name: Option<String>,
         client_id: Option<String>,
         metadata: Option<serde_json::Value>,
+        linger_after_exit: Option<bool>,
     },
 
     #[serde(rename = "rename_pty")]
@@ -445,6 +556,8 @@ struct PtySession {
     scrollback: RwLock<String>,
     output_tx: broadcast::Sender<String>,
     input_tx: std::sync::mpsc::SyncSender<Vec<u8>>, // Bounded channel for backpressure
+    /// Server event stream, for echoing input when the session opts in
+    event_tx: broadcast::Sender<ServerEvent>,
     pid: u32,
     metadata: RwLock<Option<serde_json::Value>>,
     /// DA (Device Attributes) filter to prevent feedback loops with nested terminals.
@@ -454,14 +567,29 @@ struct PtySession {
     /// Virtual terminal emulator for tracking terminal state.
     /// Provides server-side ANSI sequence parsing and grid-based storage.
     terminal: Mutex<VirtualTerminal>,
+    /// Keep the session around after the process exits (until explicitly deleted).
+    linger_after_exit: bool,
+    /// When the PTY last produced output
+    last_output: Mutex<Instant>,
+    /// Quiet period after which health is reported as idle or hung (None = not reported)
+    hung_after: Option<Duration>,
 }
 
 impl PtySession {
     fn to_info(&self) -> SessionInfo {
-        let alive = {
+        let (alive, foreground_pid) = {
             let mut inner = self.inner.lock();
-            inner.child.try_wait().ok().flatten().is_none()
+            let alive = inner.child.try_wait().ok().flatten().is_none();
+            #[cfg(unix)]
+            let foreground_pid = inner.master.process_group_leader().map(|pid| pid as u32);
+            #[cfg(not(unix))]
+            let foreground_pid = None;
+            (alive, foreground_pid.unwrap_or(self.pid))
         };
+        let health = self
+            .hung_after
+            .filter(|_| alive)
+            .map(|hung_after| self.health(hung_after, foreground_pid));
 
         SessionInfo {
             id: self.id.clone(),
@@ -475,6 +603,20 @@ impl PtySession {
             alive,
             pid: self.pid,
             metadata: self.metadata.read().clone(),
+            health,
+        }
+    }
+
+    /// Advisory liveness: `active` if there was output within `hung_after`, otherwise
+    /// `hung` when the foreground process is in uninterruptible sleep and `idle` if not
+    /// (or if its state can't be read).
+    fn health(&self, hung_after: Duration, foreground_pid: u32) -> SessionHealth {
+        if self.last_output.lock().elapsed() < hung_after {
+            SessionHealth::Active
+        } else if process_state(foreground_pid) == Some('D') {
+            SessionHealth::Hung
+        } else {
+            SessionHealth::Idle
         }
     }
 
@@ -483,6 +625,11 @@ impl PtySession {
         inner.child.try_wait().ok().flatten().is_none()
     }
 
+    /// Whether the session should be listed: running, or exited but lingering.
+    fn is_listed(&self) -> bool {
+        self.linger_after_exit || self.is_alive()
+    }
+
     /// Send input to the PTY via the channel.
     /// Uses a bounded channel for backpressure - if the PTY can't keep up,
     /// this will block (which is correct behavior for flow control).
@@ -490,7 +637,35 @@ impl PtySession {
         self.write_input_bytes(data.as_bytes().to_vec())
     }
 
+    /// Write pasted text, wrapped in bracketed paste guards when the application has
+    /// enabled them.
+    fn write_paste(&self, data: &str) -> Result<()> {
+        let bytes = self.terminal.lock().wrap_paste(data.as_bytes());
+        self.write_input_bytes(bytes)
+    }
+
     fn write_input_bytes(&self, data: Vec<u8>) -> Result<()> {
+        if !data.is_empty() && self.broadcasts_input() {
+            let _ = self.event_tx.send(ServerEvent::Input {
+                pty_id: self.id.clone(),
+                data: String::from_utf8_lossy(&data).into_owned(),
+            });
+        }
+        self.queue_pty_bytes(data)
+    }
+
+    /// Whether input should be echoed to the events stream (opt-in via metadata).
+    fn broadcasts_input(&self) -> bool {
+        self.metadata
+            .read()
+            .as_ref()
+            .and_then(|m| m.get("broadcast_input"))
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    /// Queue bytes for the PTY writer without treating them as user input.
+    fn queue_pty_bytes(&self, data: Vec<u8>) -> Result<()> {
         let len = data.len();
         if len == 0 {
             return Ok(());
@@ -505,7 +680,18 @@ impl PtySession {
         Ok(())
     }
 
-    fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+    /// Resize the PTY and terminal emulator, clamped to between one cell and the emulator's
+    /// maximum grid size. Returns the applied `(cols, rows)`.
+    fn resize(&self, cols: u16, rows: u16) -> Result<(u16, u16)> {
+        let (cols, rows) = {
+            let terminal = self.terminal.lock();
+            (
+                cols.min(terminal.max_cols.try_into().unwrap_or(u16::MAX))
+                    .max(1),
+                rows.min(terminal.max_rows.try_into().unwrap_or(u16::MAX))
+                    .max(1),
+            )
+        };
         *self.cols.write() = cols;
         *self.rows.write() = rows;
 
@@ -525,7 +711,7 @@ impl PtySession {
         // Resize virtual terminal emulator
         self.resize_terminal(rows as usize, cols as usize);
 
-        Ok(())
+        Ok((cols, rows))
     }
 
     fn kill(&self) {
@@ -552,6 +738,12 @@ impl PtySession {
         self.scrollback.read().clone()
     }
 
+    /// Resync frame for a terminal socket: a reset followed by a repaint of the
+    /// emulator's current state. Unlike the byte scrollback this is never cut short.
+    fn resync_frame(&self) -> String {
+        format!("\x1bc{}", self.terminal.lock().to_ansi())
+    }
+
     fn set_name(&self, name: String) {
         *self.name.write() = name;
     }
@@ -572,9 +764,23 @@ impl PtySession {
     fn process_terminal(&self, data: &[u8]) -> Vec<Vec<u8>> {
         let mut terminal = self.terminal.lock();
         terminal.process(data);
+        let dropped = std::mem::take(&mut terminal.dropped_responses);
+        if dropped > 0 {
+            warn!(
+                "[session:{}] Dropped {} terminal responses over the queue cap",
+                self.id, dropped
+            );
+        }
         terminal.drain_responses()
     }
 
+    /// Feed bytes to the terminal emulator only, dropping any replies it generates.
+    fn process_terminal_bytes(&self, data: &[u8]) {
+        let mut terminal = self.terminal.lock();
+        terminal.process(data);
+        terminal.drain_responses();
+    }
+
     /// Resize the virtual terminal emulator.
     fn resize_terminal(&self, rows: usize, cols: usize) {
         let mut terminal = self.terminal.lock();
@@ -582,15 +788,66 @@ impl PtySession {
     }
 
     /// Get the current terminal content as plain text lines.
-    fn get_terminal_content(&self) -> Vec<String> {
-        let terminal = self.terminal.lock();
-        terminal.get_lines()
+    fn get_terminal_content(&self) -> (String, usize) {
+        self.terminal_text(false)
     }
 
     /// Get the viewport content (visible area) as plain text lines.
-    fn get_terminal_viewport(&self) -> Vec<String> {
+    fn get_terminal_viewport(&self) -> (String, usize) {
+        self.terminal_text(true)
+    }
+
+    /// The last `n` logical lines (scrollback included) as plain text, plus their count.
+    fn get_terminal_tail(&self, n: usize) -> (String, usize) {
+        let lines = self.terminal.lock().tail_lines(n, true);
+        (lines.join("\n"), lines.len())
+    }
+
+    /// Trimmed rows joined with '\n', plus the row count, written straight from the grid
+    /// so large scrollbacks aren't copied into per-line strings first.
+    fn terminal_text(&self, viewport_only: bool) -> (String, usize) {
+        let terminal = self.terminal.lock();
+        let skip = if viewport_only {
+            terminal.scrollback_len()
+        } else {
+            0
+        };
+        let mut text = String::new();
+        let mut count = 0;
+        for row in terminal.lines_iter().skip(skip) {
+            if count > 0 {
+                text.push('\n');
+            }
+            let start = text.len();
+            text.extend(row.iter().map(|tc| tc.character));
+            text.truncate(start + text[start..].trim_end().len());
+            count += 1;
+        }
+        (text, count)
+    }
+
+    /// Cursor position and screen state, without rendering any content.
+    fn get_cursor_state(&self) -> serde_json::Value {
         let terminal = self.terminal.lock();
-        terminal.viewport_lines()
+        serde_json::json!({
+            "row": terminal.cursor_row(),
+            "col": terminal.cursor_col(),
+            "visible": terminal.cursor_visible,
+            "at_bottom": terminal.cursor_at_bottom(),
+            "alt_screen": terminal.is_alternate_screen(),
+            "style": cursor_style_name(terminal.cursor_style()),
+            "blink": terminal.cursor_blink,
+        })
+    }
+}
+
+/// Wire name for a DECSCUSR cursor style, as reported by the cursor endpoint.
+fn cursor_style_name(style: CursorStyle) -> &'static str {
+    match style {
+        CursorStyle::Default => "default",
+        CursorStyle::BlinkingBlock | CursorStyle::SteadyBlock => "block",
+        CursorStyle::BlinkingUnderline | CursorStyle::SteadyUnderline => "underline",
+        CursorStyle::BlinkingBar | CursorStyle::SteadyBar => "bar",
     }
 }
 
@@ -602,6 +859,19 @@ struct AppState {
     sessions: RwLock<HashMap<String, Arc<PtySession>>>,
     terminal_counter: RwLock<u32>,
     event_tx: broadcast::Sender<ServerEvent>,
+    /// Idle interval after which event sockets receive a heartbeat (None = disabled)
+    heartbeat_interval: Option<tokio::time::Duration>,
+    /// Window within which identical consecutive output chunks are sent to subscribers
+    /// only once, if they are idempotent repaints (None = disabled)
+    output_dedup_window: Option<tokio::time::Duration>,
+    /// Output frames a terminal socket may have queued before it is resynced
+    subscriber_queue_limit: usize,
+    /// Whether test-only endpoints are routed
+    enable_test_endpoints: bool,
+    /// Largest payload of a single terminal socket frame
+    max_ws_frame_bytes: usize,
+    /// Quiet period after which new sessions report `idle`/`hung` health (None = no health)
+    hung_after: Option<Duration>,
 }
 
 impl AppState {
@@ -611,6 +881,12 @@ impl AppState {
             sessions: RwLock::new(HashMap::new()),
             terminal_counter: RwLock::new(0),
             event_tx,
+            heartbeat_interval: None,
+            output_dedup_window: None,
+            subscriber_queue_limit: DEFAULT_SUBSCRIBER_QUEUE_LIMIT,
+            enable_test_endpoints: false,
+            max_ws_frame_bytes: DEFAULT_MAX_WS_FRAME_BYTES,
+            hung_after: None,
         }
     }
 
@@ -629,7 +905,7 @@ impl AppState {
         let sessions = self.sessions.read();
         let mut infos: Vec<_> = sessions
             .values()
-            .filter(|s| s.is_alive())
+            .filter(|s| s.is_listed())
             .map(|s| s.to_info())
             .collect();
         infos.sort_by_key(|s| s.index);
@@ -646,7 +922,7 @@ impl AppState {
         let sessions = self.sessions.read();
         let mut infos: Vec<_> = sessions
             .values()
-            .filter(|s| s.is_alive())
+            .filter(|s| s.is_listed())
             .map(|s| (s.id.clone(), s.get_index()))
             .collect();
         infos.sort_by_key(|(_, idx)| *idx);
@@ -743,6 +1019,22 @@ fn spawn_pty_writer_thread(
 // UTF-8 Helper
 // =============================================================================
 
+/// Whether writing `data` twice leaves the screen as writing it once, so a repeat may be
+/// dropped: it starts from an absolute position (CUP, or CR followed by an erase of the
+/// line) and never feeds a line. Typed echoes and scrolling output do not qualify.
+fn is_idempotent_repaint(data: &str) -> bool {
+    let rest = match data.strip_prefix('\r') {
+        Some(rest) => ["\x1b[K", "\x1b[0K", "\x1b[2K"]
+            .iter()
+            .find_map(|erase| rest.strip_prefix(erase)),
+        None => data.strip_prefix("\x1b[").and_then(|rest| {
+            let rest = rest.trim_start_matches(|c: char| c.is_ascii_digit() || c == ';');
+            rest.strip_prefix('H').or_else(|| rest.strip_prefix('f'))
+        }),
+    };
+    rest.is_some_and(|rest| !rest.contains(['\n', '\x0b', '\x0c']))
+}
+
 /// Find the last valid UTF-8 boundary in a byte slice.
 /// Returns the number of bytes that form complete UTF-8 characters.
 /// Any trailing incomplete sequence is not included.
@@ -803,6 +1095,8 @@ async fn spawn_pty_reader(
 
     let mut total_bytes_read: usize = 0;
     let mut read_count: usize = 0;
+    // Last chunk forwarded to subscribers and when, for output dedup
+    let mut last_sent: Option<(String, tokio::time::Instant)> = None;
 
     loop {
         // Read in a blocking task
@@ -846,12 +1140,13 @@ async fn spawn_pty_reader(
             Ok(n) => {
                 read_count += 1;
                 total_bytes_read += n;
+                *session.last_output.lock() = Instant::now();
 
                 // Process through virtual terminal emulator for state tracking
                 let responses = session.process_terminal(&buf[..n]);
                 if !responses.is_empty() {
                     for response in responses {
-                        if let Err(e) = session.write_input_bytes(response) {
+                        if let Err(e) = session.queue_pty_bytes(response) {
                             error!(
                                 "[reader:{}] Failed to send terminal response: {}",
                                 session_id, e
@@ -879,6 +1174,25 @@ async fn spawn_pty_reader(
                     // Update scrollback
                     session.append_scrollback(&data);
 
+                    // Skip repaints identical to the chunk just sent (spinners, progress
+                    // bars). Scrollback and the terminal state above still see every chunk.
+                    let now = tokio::time::Instant::now();
+                    let duplicate = match (state.output_dedup_window, &last_sent) {
+                        (Some(window), Some((last, sent_at))) => {
+                            *last == data
+                                && now.duration_since(*sent_at) < window
+                                && is_idempotent_repaint(&data)
+                        }
+                        _ => false,
+                    };
+                    if duplicate {
+                        utf8_buffer = utf8_buffer[valid_up_to..].to_vec();
+                        continue;
+                    }
+                    if state.output_dedup_window.is_some() {
+                        last_sent = Some((data.clone(), now));
+                    }
+
                     // Send to session-specific subscribers
                     let send_result = session.output_tx.send(data);
                     if send_result.is_err() {
@@ -910,28 +1224,56 @@ async fn spawn_pty_reader(
         }
     }
 
-    // Get exit code
-    let exit_code = {
-        let mut inner = session.inner.lock();
-        inner
-            .child
-            .try_wait()
-            .ok()
-            .flatten()
-            .map(|s| s.exit_code().try_into().unwrap_or(1))
-    };
+    // Get exit code. The child may not have been reaped yet right after EOF,
+    // so give it a brief grace period before giving up.
+    let mut exit_code = None;
+    for _ in 0..50 {
+        exit_code = {
+            let mut inner = session.inner.lock();
+            inner
+                .child
+                .try_wait()
+                .ok()
+                .flatten()
+                .map(|s| s.exit_code().try_into().unwrap_or(1))
+        };
+        if exit_code.is_some() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
 
     info!(
         "[reader:{}] Process exited with code: {:?}",
         session_id, exit_code
     );
 
+    let da_stats = session.da_filter.lock().stats();
+    if da_stats != FilterStats::default() {
+        info!("[reader:{}] DA filter: {:?}", session_id, da_stats);
+    }
+
     // Send exit event to terminal-specific subscribers
     // Prefix with \x00 to distinguish control messages from regular PTY output
     let exit_json = serde_json::to_string(&ServerEvent::Exit { exit_code }).unwrap_or_default();
     let exit_msg = format!("\x00{}", exit_json);
     let _ = session.output_tx.send(exit_msg);
 
+    if session.linger_after_exit {
+        // Keep scrollback and terminal state available until explicitly deleted
+        info!(
+            "[reader:{}] Session lingering after exit until deleted",
+            session_id
+        );
+        let mut changes = HashMap::new();
+        changes.insert("alive".to_string(), serde_json::json!(false));
+        state.broadcast_event(ServerEvent::PtyUpdated {
+            terminal: session.to_info(),
+            changes,
+        });
+        return;
+    }
+
     // Clean up: remove session from state and broadcast deletion
     let session_count = {
         let mut sessions = state.sessions.write();
@@ -965,18 +1307,27 @@ fn create_pty_session_inner(
 ) -> Result<(Arc<PtySession>, Box<dyn Read + Send>), ServerError> {
     // Security: Validate shell against whitelist
     let validated_shell = validate_shell(&request.shell)
-        .map_err(|e| ServerError::PtySpawnError(format!("Invalid shell: {}", e)))?;
+        .map_err(|e| ServerError::InvalidRequest(format!("Invalid shell: {}", e)))?;
 
     // Security: Validate and sanitize cwd path
     let validated_cwd = validate_cwd(&request.cwd)
-        .map_err(|e| ServerError::PtySpawnError(format!("Invalid cwd: {}", e)))?;
+        .map_err(|e| ServerError::InvalidRequest(format!("Invalid cwd: {}", e)))?;
+
+    // Pre-flight checks so a missing shell or cwd is reported as such rather than as
+    // an opaque spawn failure
+    check_spawn_target(validated_shell, &validated_cwd).map_err(ServerError::InvalidRequest)?;
+
+    // Keep the PTY in step with the emulator, which caps its grid size
+    let max_dimension = DEFAULT_MAX_GRID_DIMENSION as u16;
+    let rows = request.rows.clamp(1, max_dimension);
+    let cols = request.cols.clamp(1, max_dimension);
 
     let pty_system = native_pty_system();
 
     let pair = pty_system
         .openpty(PtySize {
-            rows: request.rows,
-            cols: request.cols,
+            rows,
+            cols,
             pixel_width: 0,
             pixel_height: 0,
         })
@@ -1043,18 +1394,19 @@ fn create_pty_session_inner(
         name: RwLock::new(name),
         index: RwLock::new(index),
         created_at,
-        cols: RwLock::new(request.cols),
-        rows: RwLock::new(request.rows),
+        cols: RwLock::new(cols),
+        rows: RwLock::new(rows),
         scrollback: RwLock::new(String::new()),
         output_tx,
         input_tx,
+        event_tx: state.event_tx.clone(),
         pid,
         metadata: RwLock::new(request.metadata.clone()),
         da_filter: Mutex::new(DaFilter::new()),
-        terminal: Mutex::new(VirtualTerminal::new(
-            request.rows as usize,
-            request.cols as usize,
-        )),
+        terminal: Mutex::new(VirtualTerminal::new(rows as usize, cols as usize)),
+        linger_after_exit: request.linger_after_exit,
+        last_output: Mutex::new(Instant::now()),
+        hung_after: state.hung_after,
     });
 
     Ok((session, reader))
@@ -1076,10 +1428,34 @@ async fn health(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }))
 }
 
-async fn list_sessions(State(state): State<Arc<AppState>>) -> impl IntoResponse {
-    Json(serde_json::json!({
-        "sessions": state.get_ordered_sessions()
-    }))
+/// Query parameters of the form `metadata.<key>=<value>` restrict the listing to sessions
+/// whose metadata has every given key at the top level with an equal value. String values
+/// compare as-is; other values are parsed as JSON before comparing (e.g. `true`, `3`).
+async fn list_sessions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let filters: Vec<(&str, &str)> = params
+        .iter()
+        .filter_map(|(key, value)| Some((key.strip_prefix("metadata.")?, value.as_str())))
+        .collect();
+    let sessions: Vec<SessionInfo> = state
+        .get_ordered_sessions()
+        .into_iter()
+        .filter(|info| metadata_matches(info.metadata.as_ref(), &filters))
+        .collect();
+    Json(serde_json::json!({ "sessions": sessions }))
+}
+
+fn metadata_matches(metadata: Option<&serde_json::Value>, filters: &[(&str, &str)]) -> bool {
+    filters
+        .iter()
+        .all(|(key, expected)| match metadata.and_then(|m| m.get(key)) {
+            Some(serde_json::Value::String(value)) => value == expected,
+            Some(value) => serde_json::from_str::<serde_json::Value>(expected)
+                .is_ok_and(|expected| &expected == value),
+            None => false,
+        })
 }
 
 async fn create_session(
@@ -1242,18 +1618,28 @@ async fn capture_session(
         .map(|v| v == "true")
         .unwrap_or(false);
     let viewport_only = params.get("viewport").map(|v| v == "true").unwrap_or(false);
+    let tail = match params.get("tail") {
+        Some(n) => Some(n.parse::<usize>().map_err(|_| {
+            ServerError::InvalidRequest(format!("tail must be a line count, got {:?}", n))
+        })?),
+        None => None,
+    };
 
     if processed {
         // Return ANSI-processed terminal content (plain text)
-        let lines = if viewport_only {
+        let (content, lines) = if let Some(n) = tail {
+            session.get_terminal_tail(n)
+        } else if viewport_only {
             session.get_terminal_viewport()
         } else {
             session.get_terminal_content()
         };
-        let content = lines.join("\n");
+        // A full-screen app has no meaningful scrollback; lets the UI switch to viewport mode
+        let alt_screen = session.terminal.lock().is_alternate_screen();
         Ok(Json(serde_json::json!({
             "content": content,
-            "lines": lines.len(),
+            "lines": lines,
+            "alt_screen": alt_screen,
             "processed": true
         })))
     } else {
@@ -1267,6 +1653,18 @@ async fn capture_session(
     }
 }
 
+/// Cheap poll of the cursor (0-based row/col) and screen state, e.g. to detect a prompt.
+async fn get_session_cursor(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+) -> Result<impl IntoResponse, ServerError> {
+    let sessions = state.sessions.read();
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
+    Ok(Json(session.get_cursor_state()))
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct ResizeRequest {
     cols: u16,
@@ -1283,7 +1681,7 @@ async fn resize_session(
         .get(&session_id)
         .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
 
-    session
+    let (cols, rows) = session
         .resize(request.cols, request.rows)
         .map_err(|e| ServerError::PtySpawnError(e.to_string()))?;
 
@@ -1291,8 +1689,8 @@ async fn resize_session(
 
     // Broadcast update
     let mut changes = HashMap::new();
-    changes.insert("cols".to_string(), serde_json::json!(request.cols));
-    changes.insert("rows".to_string(), serde_json::json!(request.rows));
+    changes.insert("cols".to_string(), serde_json::json!(cols));
+    changes.insert("rows".to_string(), serde_json::json!(rows));
 
     drop(sessions); // Release lock before broadcast
     state.broadcast_event(ServerEvent::PtyUpdated {
@@ -1306,6 +1704,9 @@ async fn resize_session(
 #[derive(Debug, Clone, Deserialize)]
 struct InputRequest {
     data: String,
+    /// Treat the data as a paste (bracketed if the application asked for it)
+    #[serde(default)]
+    paste: bool,
 }
 
 async fn send_input(
@@ -1318,13 +1719,59 @@ async fn send_input(
         .get(&session_id)
         .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
 
+    if request.paste {
+        session.write_paste(&request.data)
+    } else {
+        session.write_input(&request.data)
+    }
+    .map_err(|e| ServerError::PtySpawnError(e.to_string()))?;
+
+    Ok(Json(serde_json::json!({
+        "status": "ok",
+        "bytes": request.data.len()
+    })))
+}
+
+/// Write the request body to the PTY as-is, for control sequences and binary input
+/// that are awkward to express as JSON strings.
+async fn send_raw_input(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    body: axum::body::Bytes,
+) -> Result<impl IntoResponse, ServerError> {
+    let sessions = state.sessions.read();
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
+
+    let len = body.len();
     session
-        .write_input(&request.data)
+        .write_input_bytes(body.to_vec())
         .map_err(|e| ServerError::PtySpawnError(e.to_string()))?;
 
     Ok(Json(serde_json::json!({
         "status": "ok",
-        "bytes": request.data.len()
+        "bytes": len
+    })))
+}
+
+/// Test-only: feed raw bytes straight into the session's terminal emulator, bypassing the
+/// PTY. Replies the emulator generates (e.g. DSR reports) are discarded.
+async fn process_terminal_bytes(
+    State(state): State<Arc<AppState>>,
+    Path(session_id): Path<String>,
+    body: axum::body::Bytes,
+) -> Result<impl IntoResponse, ServerError> {
+    let sessions = state.sessions.read();
+    let session = sessions
+        .get(&session_id)
+        .ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
+
+    session.process_terminal_bytes(&body);
+
+    Ok(Json(serde_json::json!({
+        "status": "ok",
+        "bytes": body.len()
     })))
 }
 
@@ -1442,9 +1889,32 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
 
     // Spawn task to forward events to WebSocket
     let ws_id_clone = ws_id.clone();
+    let heartbeat_interval = state.heartbeat_interval;
     let send_task = tokio::spawn(async move {
         let mut event_count = 0usize;
-        while let Ok(event) = event_rx.recv().await {
+        loop {
+            let recv = match heartbeat_interval {
+                Some(interval) => match tokio::time::timeout(interval, event_rx.recv()).await {
+                    Ok(recv) => recv,
+                    Err(_) => Ok(ServerEvent::Heartbeat),
+                },
+                None => event_rx.recv().await,
+            };
+            let Ok(event) = recv else {
+                break;
+            };
+            if matches!(event, ServerEvent::Heartbeat) {
+                if let Ok(json) = serde_json::to_string(&event) {
+                    if sender.send(Message::Text(json)).await.is_err() {
+                        warn!(
+                            "[events-ws:{}] Failed to send heartbeat, closing",
+                            ws_id_clone
+                        );
+                        break;
+                    }
+                }
+                continue;
+            }
             event_count += 1;
             let event_type = match &event {
                 ServerEvent::StateSync { .. } => "state_sync",
@@ -1454,6 +1924,8 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                 ServerEvent::Output { .. } => "output",
                 ServerEvent::Exit { .. } => "exit",
                 ServerEvent::Error { .. } => "error",
+                ServerEvent::Heartbeat => "heartbeat",
+                ServerEvent::Input { .. } => "input",
             };
             info!(
                 "[events-ws:{}] Forwarding event #{}: {}",
@@ -1505,6 +1977,7 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                 name,
                 client_id,
                 metadata,
+                linger_after_exit,
             } => {
                 let request = CreateSessionRequest {
                     shell: shell.unwrap_or_else(default_shell),
@@ -1515,6 +1988,7 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
                     name,
                     client_id: client_id.clone(),
                     metadata,
+                    linger_after_exit: linger_after_exit.unwrap_or(false),
                 };
 
                 match create_pty_session_inner(&state, &request) {
@@ -1613,9 +2087,67 @@ async fn handle_event_websocket(socket: WebSocket, state: Arc<AppState>) {
     info!("Event subscriber disconnected");
 }
 
+/// WebSocket subprotocols accepted on terminal sockets, in order of preference.
+/// `binary` is the default (raw bytes in binary frames); `base64` exchanges
+/// base64-encoded text frames for clients that cannot handle binary frames.
+const TERMINAL_WS_PROTOCOLS: [&str; 2] = ["binary", "base64"];
+
+/// Close code sent when a terminal socket's session was deleted before the upgrade
+/// completed (application-defined range, mirroring HTTP 404).
+const WS_CLOSE_SESSION_NOT_FOUND: u16 = 4404;
+
+/// Framing used for PTY data on a terminal WebSocket, chosen by subprotocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TerminalFraming {
+    Binary,
+    Base64,
+}
+
+impl TerminalFraming {
+    fn from_socket(socket: &WebSocket) -> Self {
+        match socket.protocol().and_then(|p| p.to_str().ok()) {
+            Some("base64") => TerminalFraming::Base64,
+            _ => TerminalFraming::Binary,
+        }
+    }
+
+    /// Wrap PTY output bytes in a frame for this framing mode.
+    fn encode(self, data: Vec<u8>) -> Message {
+        match self {
+            TerminalFraming::Binary => Message::Binary(data),
+            TerminalFraming::Base64 => Message::Text(BASE64.encode(data)),
+        }
+    }
+
+    /// Wrap PTY output in as many consecutive frames as needed to keep each payload
+    /// within `max_frame` bytes. Clients reassemble by simply concatenating them.
+    fn encode_chunked(self, data: Vec<u8>, max_frame: usize) -> Vec<Message> {
+        let chunk_len = match self {
+            TerminalFraming::Binary => max_frame,
+            // Base64 turns every 3 bytes into 4
+            TerminalFraming::Base64 => max_frame / 4 * 3,
+        }
+        .max(1);
+        if data.len() <= chunk_len {
+            return vec![self.encode(data)];
+        }
+        data.chunks(chunk_len)
+            .map(|chunk| self.encode(chunk.to_vec()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct TerminalWsParams {
+    /// Attach as an observer: output is delivered but input and resize frames are dropped.
+    #[serde(default)]
+    readonly: bool,
+}
+
 async fn websocket_terminal(
     ws: WebSocketUpgrade,
     Path(session_id): Path<String>,
+    Query(params): Query<TerminalWsParams>,
     State(state): State<Arc<AppState>>,
 ) -> Result<impl IntoResponse, ServerError> {
     // Verify session exists and get data
@@ -1634,24 +2166,139 @@ async fn websocket_terminal(
 
     let session = session.ok_or_else(|| ServerError::SessionNotFound(session_id.clone()))?;
 
-    Ok(ws.on_upgrade(move |socket| {
-        handle_terminal_websocket(socket, session, scrollback, output_rx)
-    }))
+    Ok(ws
+        .protocols(TERMINAL_WS_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            handle_terminal_websocket(
+                socket,
+                state,
+                session,
+                scrollback,
+                output_rx,
+                params.readonly,
+            )
+        }))
+}
+
+/// Output frames waiting to be written to one terminal socket.
+///
+/// Bounded so a slow client only ever holds `limit` frames; beyond that its backlog is
+/// replaced by a single resync frame (see `PtySession::resync_frame`).
+struct SubscriberQueue {
+    state: Mutex<QueueState>,
+    notify: tokio::sync::Notify,
+    limit: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<String>,
+    closed: bool,
+}
+
+impl SubscriberQueue {
+    fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            notify: tokio::sync::Notify::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    /// Queue a frame. Returns false (and queues nothing) when the queue is full.
+    fn push(&self, data: String) -> bool {
+        {
+            let mut state = self.state.lock();
+            if state.frames.len() >= self.limit {
+                return false;
+            }
+            state.frames.push_back(data);
+        }
+        self.notify.notify_one();
+        true
+    }
+
+    /// Drop everything queued and replace it with a single resync frame.
+    fn resync(&self, frame: String) {
+        {
+            let mut state = self.state.lock();
+            state.frames.clear();
+            state.frames.push_back(frame);
+        }
+        self.notify.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Next frame to send, or None once the queue is closed and drained.
+    async fn pop(&self) -> Option<String> {
+        loop {
+            {
+                let mut state = self.state.lock();
+                if let Some(frame) = state.frames.pop_front() {
+                    return Some(frame);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// Whether a text frame is the `{"type":"resync"}` control message, which asks for the
+/// queued output to be replaced by a full repaint (see `PtySession::resync_frame`).
+fn is_resync_request(text: &str) -> bool {
+    text.starts_with('{')
+        && serde_json::from_str::<serde_json::Value>(text)
+            .is_ok_and(|ctrl| ctrl.get("type").and_then(|t| t.as_str()) == Some("resync"))
 }
 
 async fn handle_terminal_websocket(
-    socket: WebSocket,
+    mut socket: WebSocket,
+    state: Arc<AppState>,
     session: Arc<PtySession>,
     scrollback: String,
     mut output_rx: broadcast::Receiver<String>,
+    readonly: bool,
 ) {
-    let (mut sender, mut receiver) = socket.split();
     let session_id = session.id.clone();
 
+    // The session may have been deleted between the HTTP check and the upgrade; tell the
+    // client why instead of dropping the connection
+    let still_exists = state
+        .sessions
+        .read()
+        .get(&session_id)
+        .is_some_and(|current| Arc::ptr_eq(current, &session));
+    if !still_exists {
+        warn!(
+            "[term-ws:{}] Session deleted before the upgrade completed",
+            session_id
+        );
+        let close = CloseFrame {
+            code: WS_CLOSE_SESSION_NOT_FOUND,
+            reason: "session not found".into(),
+        };
+        let _ = socket.send(Message::Close(Some(close))).await;
+        return;
+    }
+
+    let queue_limit = state.subscriber_queue_limit;
+    let max_frame = state.max_ws_frame_bytes;
+    let framing = TerminalFraming::from_socket(&socket);
+    let (mut sender, mut receiver) = socket.split();
+
     info!(
-        "[term-ws:{}] Terminal WebSocket connected (scrollback: {} bytes)",
+        "[term-ws:{}] Terminal WebSocket connected (scrollback: {} bytes, framing: {:?}, readonly: {})",
         session_id,
-        scrollback.len()
+        scrollback.len(),
+        framing,
+        readonly
     );
 
     // Send scrollback as raw binary (xterm expects raw data)
@@ -1661,32 +2308,61 @@ async fn handle_terminal_websocket(
             session_id,
             scrollback.len()
         );
-        if sender
-            .send(Message::Binary(scrollback.into_bytes()))
-            .await
-            .is_err()
-        {
-            warn!("[term-ws:{}] Failed to send scrollback", session_id);
-            return;
+        for frame in framing.encode_chunked(scrollback.into_bytes(), max_frame) {
+            if sender.send(frame).await.is_err() {
+                warn!("[term-ws:{}] Failed to send scrollback", session_id);
+                return;
+            }
         }
     }
 
+    // Move broadcast output into this socket's own queue so a slow client never makes the
+    // session's broadcast lag; if the queue overflows it is collapsed into a resync.
+    let queue = Arc::new(SubscriberQueue::new(queue_limit));
+    let resync_queue = queue.clone();
+    let pump_queue = queue.clone();
+    let pump_session = session.clone();
+    let pump_task = tokio::spawn(async move {
+        loop {
+            let overflowed = match output_rx.recv().await {
+                Ok(data) => !pump_queue.push(data),
+                Err(broadcast::error::RecvError::Lagged(_)) => true,
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            if overflowed {
+                // Everything still buffered in the broadcast is already in the emulator
+                while let Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) =
+                    output_rx.try_recv()
+                {}
+                warn!(
+                    "[term-ws:{}] Subscriber fell behind, resyncing from the emulator",
+                    pump_session.id
+                );
+                pump_queue.resync(pump_session.resync_frame());
+            }
+        }
+        pump_queue.close();
+    });
+
     // Spawn task to forward PTY output to WebSocket as raw binary
     let session_id_clone = session_id.clone();
     let send_task = tokio::spawn(async move {
         let mut output_count = 0usize;
         let mut total_bytes = 0usize;
 
-        while let Ok(data) = output_rx.recv().await {
+        while let Some(data) = queue.pop().await {
             output_count += 1;
             total_bytes += data.len();
 
-            // Send raw binary data (xterm AttachAddon expects this)
-            if sender
-                .send(Message::Binary(data.into_bytes()))
-                .await
-                .is_err()
-            {
+            // Send raw binary data (xterm AttachAddon expects this) unless base64 was negotiated
+            let mut failed = false;
+            for frame in framing.encode_chunked(data.into_bytes(), max_frame) {
+                if sender.send(frame).await.is_err() {
+                    failed = true;
+                    break;
+                }
+            }
+            if failed {
                 warn!(
                     "[term-ws:{}] Failed to send output, closing",
                     session_id_clone
@@ -1706,6 +2382,21 @@ async fn handle_terminal_websocket(
     let mut input_bytes = 0usize;
 
     while let Some(msg) = receiver.next().await {
+        // A resync only sends output back, so read-only subscribers may ask for one too
+        if let Ok(Message::Text(text)) = &msg {
+            if is_resync_request(text) {
+                info!("[term-ws:{}] Client requested resync", session_id);
+                resync_queue.resync(session.resync_frame());
+                continue;
+            }
+        }
+        if readonly && matches!(msg, Ok(Message::Binary(_)) | Ok(Message::Text(_))) {
+            warn!(
+                "[term-ws:{}] Ignoring frame from read-only subscriber",
+                session_id
+            );
+            continue;
+        }
         match msg {
             Ok(Message::Binary(data)) => {
                 // Raw binary input from xterm
@@ -1734,12 +2425,15 @@ async fn handle_terminal_websocket(
                         if let Some(typ) = ctrl.get("type").and_then(|t| t.as_str()) {
                             match typ {
                                 "resize" => {
-                                    let cols =
-                                        ctrl.get("cols").and_then(|c| c.as_u64()).unwrap_or(80)
-                                            as u16;
-                                    let rows =
-                                        ctrl.get("rows").and_then(|r| r.as_u64()).unwrap_or(24)
-                                            as u16;
+                                    let dimension = |key: &str, default: u16| {
+                                        ctrl.get(key)
+                                            .and_then(|v| v.as_u64())
+                                            .map_or(default, |v| {
+                                                u16::try_from(v).unwrap_or(u16::MAX)
+                                            })
+                                    };
+                                    let cols = dimension("cols", 80);
+                                    let rows = dimension("rows", 24);
                                     info!("[term-ws:{}] Resize: {}x{}", session_id, cols, rows);
                                     if let Err(e) = session.resize(cols, rows) {
                                         error!(
@@ -1752,7 +2446,16 @@ async fn handle_terminal_websocket(
                                     if let Some(data) = ctrl.get("data").and_then(|d| d.as_str()) {
                                         input_count += 1;
                                         input_bytes += data.len();
-                                        if let Err(e) = session.write_input(data) {
+                                        let paste = ctrl
+                                            .get("paste")
+                                            .and_then(|p| p.as_bool())
+                                            .unwrap_or(false);
+                                        let written = if paste {
+                                            session.write_paste(data)
+                                        } else {
+                                            session.write_input(data)
+                                        };
+                                        if let Err(e) = written {
                                             error!(
                                                 "[term-ws:{}] Failed to write to PTY: {}",
                                                 session_id, e
@@ -1766,6 +2469,22 @@ async fn handle_terminal_websocket(
                         }
                     }
                 }
+                if framing == TerminalFraming::Base64 {
+                    match BASE64.decode(text.trim()) {
+                        Ok(data) => {
+                            input_count += 1;
+                            input_bytes += data.len();
+                            if let Err(e) = session.write_input_bytes(data) {
+                                error!("[term-ws:{}] Failed to write to PTY: {}", session_id, e);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("[term-ws:{}] Invalid base64 input: {}", session_id, e);
+                        }
+                    }
+                    continue;
+                }
+
                 // Raw text input from xterm
                 input_count += 1;
                 input_bytes += text.len();
@@ -1798,6 +2517,7 @@ async fn handle_terminal_websocket(
     }
 
     send_task.abort();
+    pump_task.abort();
     info!(
         "[term-ws:{}] Disconnected. Total input: {} messages, {} bytes",
         session_id, input_count, input_bytes
@@ -1814,7 +2534,26 @@ async fn main() -> Result<()> {
 
     match cli.command {
         // Server mode
-        Some(Commands::Server { host, port }) => run_server(&host, port).await,
+        Some(Commands::Server {
+            host,
+            port,
+            heartbeat_secs,
+            dedup_output_ms,
+            subscriber_queue_frames,
+            max_ws_frame,
+            enable_test_endpoints,
+            detect_hung_secs,
+        }) => {
+            let options = ServerOptions {
+                heartbeat_secs,
+                dedup_output_ms,
+                subscriber_queue_frames,
+                max_ws_frame,
+                enable_test_endpoints,
+                detect_hung_secs,
+            };
+            run_server(&host, port, options).await
+        }
 
         // No command = server mode (for backwards compatibility)
         None => {
@@ -1823,7 +2562,37 @@ async fn main() -> Result<()> {
                 .unwrap_or_else(|_| "39383".to_string())
                 .parse()
                 .context("Invalid PTY_SERVER_PORT")?;
-            run_server(&host, port).await
+            let heartbeat_secs: u64 = env::var("PTY_HEARTBEAT_SECS")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()
+                .context("Invalid PTY_HEARTBEAT_SECS")?;
+            let dedup_output_ms: u64 = env::var("PTY_DEDUP_OUTPUT_MS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("Invalid PTY_DEDUP_OUTPUT_MS")?;
+            let subscriber_queue_frames: usize = env::var("PTY_SUBSCRIBER_QUEUE_FRAMES")
+                .unwrap_or_else(|_| DEFAULT_SUBSCRIBER_QUEUE_LIMIT.to_string())
+                .parse()
+                .context("Invalid PTY_SUBSCRIBER_QUEUE_FRAMES")?;
+            let max_ws_frame: usize = env::var("PTY_MAX_WS_FRAME")
+                .unwrap_or_else(|_| DEFAULT_MAX_WS_FRAME_BYTES.to_string())
+                .parse()
+                .context("Invalid PTY_MAX_WS_FRAME")?;
+            let enable_test_endpoints = env::var("PTY_ENABLE_TEST_ENDPOINTS")
+                .is_ok_and(|v| matches!(v.as_str(), "1" | "true"));
+            let detect_hung_secs: u64 = env::var("PTY_DETECT_HUNG_SECS")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()
+                .context("Invalid PTY_DETECT_HUNG_SECS")?;
+            let options = ServerOptions {
+                heartbeat_secs,
+                dedup_output_ms,
+                subscriber_queue_frames,
+                max_ws_frame,
+                enable_test_endpoints,
+                detect_hung_secs,
+            };
+            run_server(&host, port, options).await
         }
 
         // Client commands
@@ -1856,7 +2625,26 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn run_server(host: &str, port: u16) -> Result<()> {
+/// Server tuning from the command line or environment.
+struct ServerOptions {
+    heartbeat_secs: u64,
+    dedup_output_ms: u64,
+    subscriber_queue_frames: usize,
+    max_ws_frame: usize,
+    enable_test_endpoints: bool,
+    detect_hung_secs: u64,
+}
+
+async fn run_server(host: &str, port: u16, options: ServerOptions) -> Result<()> {
+    let ServerOptions {
+        heartbeat_secs,
+        dedup_output_ms,
+        subscriber_queue_frames,
+        max_ws_frame,
+        enable_test_endpoints,
+        detect_hung_secs,
+    } = options;
+
     // Debug output to ensure binary is running
     eprintln!("[pty-server] Starting...");
     std::io::Write::flush(&mut std::io::stderr()).ok();
@@ -1871,27 +2659,21 @@ async fn run_server(host: &str, port: u16) -> Result<()> {
 
     eprintln!("[pty-server] Logging initialized");
 
-    let state = Arc::new(AppState::new());
-
-    let app = Router::new()
-        // Static frontend
-        .route("/", get(index_handler))
-        .route("/index.html", get(index_handler))
-        // HTTP endpoints
-        .route("/health", get(health))
-        .route("/sessions", get(list_sessions))
-        .route("/sessions", post(create_session))
-        .route("/sessions/:session_id", patch(update_session))
-        .route("/sessions/:session_id", delete(delete_session))
-        .route("/sessions/:session_id/capture", get(capture_session))
-        .route("/sessions/:session_id/resize", post(resize_session))
-        .route("/sessions/:session_id/input", post(send_input))
-        .route("/signal", post(send_signal))
-        // WebSocket endpoints
-        .route("/ws", get(websocket_events))
-        .route("/sessions/:session_id/ws", get(websocket_terminal))
-        .layer(CorsLayer::permissive())
-        .with_state(state);
+    let mut state = AppState::new();
+    state.heartbeat_interval =
+        (heartbeat_secs > 0).then(|| tokio::time::Duration::from_secs(heartbeat_secs));
+    state.output_dedup_window =
+        (dedup_output_ms > 0).then(|| tokio::time::Duration::from_millis(dedup_output_ms));
+    state.subscriber_queue_limit = subscriber_queue_frames.max(1);
+    state.max_ws_frame_bytes = max_ws_frame.max(1);
+    state.enable_test_endpoints = enable_test_endpoints;
+    state.hung_after = (detect_hung_secs > 0).then(|| Duration::from_secs(detect_hung_secs));
+    if enable_test_endpoints {
+        warn!(
+            "Test endpoints enabled: POST /sessions/:id/process feeds terminal emulators directly"
+        );
+    }
+    let app = build_router(Arc::new(state));
 
     let addr = format!("{}:{}", host, port);
     info!("Starting PTY server on {}", addr);
@@ -1909,6 +2691,37 @@ async fn run_server(host: &str, port: u16) -> Result<()> {
     Ok(())
 }
 
+fn build_router(state: Arc<AppState>) -> Router {
+    let mut router = Router::new();
+    if state.enable_test_endpoints {
+        router = router.route(
+            "/sessions/:session_id/process",
+            post(process_terminal_bytes),
+        );
+    }
+    router
+        // Static frontend
+        .route("/", get(index_handler))
+        .route("/index.html", get(index_handler))
+        // HTTP endpoints
+        .route("/health", get(health))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions", post(create_session))
+        .route("/sessions/:session_id", patch(update_session))
+        .route("/sessions/:session_id", delete(delete_session))
+        .route("/sessions/:session_id/capture", get(capture_session))
+        .route("/sessions/:session_id/cursor", get(get_session_cursor))
+        .route("/sessions/:session_id/resize", post(resize_session))
+        .route("/sessions/:session_id/input", post(send_input))
+        .route("/sessions/:session_id/input/raw", post(send_raw_input))
+        .route("/signal", post(send_signal))
+        // WebSocket endpoints
+        .route("/ws", get(websocket_events))
+        .route("/sessions/:session_id/ws", get(websocket_terminal))
+        .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
 // =============================================================================
 // Tests
 // =============================================================================
@@ -1968,6 +2781,125 @@ mod tests {
         assert_eq!(response.status(), StatusCode::OK);
     }
 
+    async fn create_session_error(body: serde_json::Value) -> (StatusCode, String) {
+        let response = create_test_app()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/sessions")
+                    .header("content-type", "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        (status, json["error"].as_str().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_create_session_reports_missing_cwd() {
+        let (status, error) = create_session_error(serde_json::json!({
+            "shell": "/bin/sh",
+            "cwd": "/tmp/cmux-pty-missing-cwd-test",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            error.contains("cwd does not exist: /tmp/cmux-pty-missing-cwd-test"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_create_session_reports_missing_shell() {
+        // Any allowed shell that is not installed on this machine will do
+        let Some((_, shell)) = SHELL_NAME_MAPPINGS
+            .iter()
+            .find(|(_, path)| !std::path::Path::new(path).exists())
+        else {
+            return;
+        };
+        let (status, error) = create_session_error(serde_json::json!({
+            "shell": shell,
+            "cwd": "/tmp",
+        }))
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(
+            error.contains(&format!("shell not found: {}", shell)),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions_filtered_by_metadata() {
+        let state = Arc::new(AppState::new());
+        let mut created = Vec::new();
+        for metadata in [
+            serde_json::json!({"type": "agent", "pinned": true}),
+            serde_json::json!({"type": "shell"}),
+            serde_json::json!({"type": "agent"}),
+        ] {
+            let request = CreateSessionRequest {
+                shell: "/bin/sh".to_string(),
+                cwd: "/tmp".to_string(),
+                metadata: Some(metadata),
+                ..Default::default()
+            };
+            let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+            state
+                .sessions
+                .write()
+                .insert(session.id.clone(), session.clone());
+            created.push(session);
+        }
+
+        let app = Router::new()
+            .route("/sessions", get(list_sessions))
+            .with_state(state.clone());
+        let list = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                let value: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                value["sessions"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|s| s["id"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            list("/sessions?metadata.type=agent").await,
+            [created[0].id.clone(), created[2].id.clone()]
+        );
+        assert_eq!(
+            list("/sessions?metadata.type=agent&metadata.pinned=true").await,
+            [created[0].id.clone()]
+        );
+        assert_eq!(list("/sessions").await.len(), 3);
+
+        for session in created {
+            session.kill();
+        }
+    }
+
     #[tokio::test]
     async fn test_create_session() {
         let app = create_test_app();
@@ -2237,6 +3169,101 @@ mod tests {
         session.kill();
     }
 
+    #[tokio::test]
+    async fn test_cursor_endpoint_tracks_prompt() {
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        let mut output_rx = session.output_tx.subscribe();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        // Replace the prompt with a known one at a known position
+        session
+            .write_input("PS1='ready> '; printf '\\033[H\\033[2J'\n")
+            .unwrap();
+        let mut output = String::new();
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while !output.ends_with("ready> ") {
+            let chunk = tokio::time::timeout_at(deadline, output_rx.recv())
+                .await
+                .unwrap_or_else(|_| panic!("timed out, got {:?}", output));
+            output.push_str(&chunk.unwrap());
+        }
+
+        let app = Router::new()
+            .route("/sessions/:session_id/cursor", get(get_session_cursor))
+            .with_state(state.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/sessions/{}/cursor", session_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let cursor: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            cursor,
+            serde_json::json!({
+                "row": 0,
+                "col": "ready> ".len(),
+                "visible": true,
+                "at_bottom": true,
+                "alt_screen": false,
+                "style": "default",
+                "blink": true,
+            })
+        );
+
+        session.kill();
+    }
+
+    #[test]
+    fn test_proc_stat_state_parsing() {
+        assert_eq!(parse_proc_stat_state("42 (sh) S 1 42 42"), Some('S'));
+        // Command names may contain spaces and parentheses
+        assert_eq!(parse_proc_stat_state("42 (a) (b c) D 1 42"), Some('D'));
+        assert_eq!(parse_proc_stat_state(""), None);
+        // No such process (or no /proc at all)
+        assert_eq!(process_state(u32::MAX), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_sleeping_child_reports_idle_health() {
+        let mut state = AppState::new();
+        state.hung_after = Some(Duration::from_millis(300));
+        let state = Arc::new(state);
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+        assert_eq!(session.to_info().health, Some(SessionHealth::Active));
+
+        session.write_input("sleep 30\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(session.to_info().health, Some(SessionHealth::Idle));
+
+        session.kill();
+    }
+
     /// Test resize endpoint
     #[tokio::test]
     async fn test_resize_endpoint() {
@@ -2266,6 +3293,7 @@ mod tests {
             .with_state(state.clone());
 
         let response = app
+            .clone()
             .oneshot(
                 Request::builder()
                     .method("POST")
@@ -2283,6 +3311,47 @@ mod tests {
         assert_eq!(*session.cols.read(), 120);
         assert_eq!(*session.rows.read(), 40);
 
+        // Absurd sizes are clamped rather than allocated
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/resize", session_id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"cols": 60000, "rows": 60000}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let max = DEFAULT_MAX_GRID_DIMENSION;
+        assert_eq!(*session.cols.read() as usize, max);
+        assert_eq!(*session.rows.read() as usize, max);
+        {
+            let terminal = session.terminal.lock();
+            assert_eq!((terminal.rows(), terminal.cols()), (max, max));
+        }
+
+        // Zero sizes are raised to a single cell rather than collapsing the grid
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/resize", session_id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"cols": 0, "rows": 0}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!((*session.cols.read(), *session.rows.read()), (1, 1));
+        {
+            let terminal = session.terminal.lock();
+            assert_eq!((terminal.rows(), terminal.cols()), (1, 1));
+        }
+
         session.kill();
     }
 
@@ -2329,4 +3398,834 @@ mod tests {
 
         session.kill();
     }
+
+    /// Test raw input endpoint passes control bytes through unchanged
+    #[tokio::test]
+    async fn test_raw_input_endpoint() {
+        let state = Arc::new(AppState::new());
+
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            linger_after_exit: true,
+            ..Default::default()
+        };
+
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        let reader_task = tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        // od dumps whatever reaches the PTY until the trailing ^D
+        session
+            .write_input("stty -echo; od -An -tx1; exit\n")
+            .unwrap();
+
+        let app = Router::new()
+            .route("/sessions/:session_id/input/raw", post(send_raw_input))
+            .route("/sessions/:session_id/capture", get(capture_session))
+            .with_state(state.clone());
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/input/raw", session_id))
+                    .header("content-type", "application/octet-stream")
+                    .body(Body::from(b"A\x01\x02z\n\x04".to_vec()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), reader_task)
+            .await
+            .expect("process did not exit")
+            .unwrap();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/sessions/{}/capture?processed=true", session_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let content = json["content"].as_str().unwrap();
+        assert!(
+            content.contains("41 01 02 7a 0a"),
+            "unexpected capture: {:?}",
+            content
+        );
+    }
+
+    /// Test pasted input is bracketed once the application enables bracketed paste
+    #[tokio::test]
+    async fn test_paste_input_is_bracketed() {
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            linger_after_exit: true,
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        let reader_task = tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        session
+            .write_input("stty -echo; od -An -tx1; exit\n")
+            .unwrap();
+        // As if the application had sent DECSET 2004
+        session.process_terminal_bytes(b"\x1b[?2004h");
+
+        let app = Router::new()
+            .route("/sessions/:session_id/input", post(send_input))
+            .with_state(state.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/input", session_id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"data": "a\u001b[201~b", "paste": true}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        session.write_input("\n\x04").unwrap();
+
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), reader_task)
+            .await
+            .expect("process did not exit")
+            .unwrap();
+        let (content, _) = session.get_terminal_content();
+        // ESC [ 2 0 0 ~ a b ESC [ 2 0 1 ~ - the embedded terminator is gone
+        assert!(
+            content.contains("1b 5b 32 30 30 7e 61 62 1b 5b 32 30 31 7e 0a"),
+            "unexpected capture: {:?}",
+            content
+        );
+    }
+
+    /// Test identical consecutive output chunks reach subscribers once per dedup window
+    #[tokio::test]
+    async fn test_output_dedup_suppresses_repeated_frames() {
+        let mut state = AppState::new();
+        state.output_dedup_window = Some(tokio::time::Duration::from_secs(5));
+        let state = Arc::new(state);
+
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let mut output_rx = session.output_tx.subscribe();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        session
+            .write_input(
+                "for i in 1 2 3 4 5 6 7 8; do printf '\\r\\033[K|'; sleep 0.05; done; echo; echo fin$((1 + 1))\n",
+            )
+            .unwrap();
+
+        let mut frames = Vec::new();
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while !frames.iter().any(|f: &String| f.contains("fin2")) {
+            let frame = tokio::time::timeout_at(deadline, output_rx.recv()).await;
+            let frame = frame.unwrap_or_else(|_| panic!("timed out, got {:?}", frames));
+            frames.push(frame.unwrap());
+        }
+        let spinner_frames = frames.iter().filter(|f| f.as_str() == "\r\x1b[K|").count();
+        assert!(spinner_frames >= 1, "frames: {:?}", frames);
+        assert!(spinner_frames < 8, "frames: {:?}", frames);
+
+        session.kill();
+    }
+
+    /// Repeated output that is not a repaint, such as typed echoes, is never deduplicated
+    #[tokio::test]
+    async fn test_output_dedup_keeps_repeated_echoes() {
+        let mut state = AppState::new();
+        state.output_dedup_window = Some(tokio::time::Duration::from_secs(5));
+        let state = Arc::new(state);
+
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let mut output_rx = session.output_tx.subscribe();
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+        tokio::time::sleep(tokio::time::Duration::from_millis(300)).await;
+
+        for _ in 0..3 {
+            session.write_input("a").unwrap();
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+        }
+
+        let mut output = String::new();
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while !output.contains("aaa") {
+            let frame = tokio::time::timeout_at(deadline, output_rx.recv()).await;
+            let frame = frame.unwrap_or_else(|_| panic!("timed out, got {:?}", output));
+            output.push_str(&frame.unwrap());
+        }
+
+        session.kill();
+    }
+
+    #[test]
+    fn test_idempotent_repaint_detection() {
+        assert!(is_idempotent_repaint("\r\x1b[K50%"));
+        assert!(is_idempotent_repaint("\x1b[3;1H\x1b[2Kbar"));
+        assert!(is_idempotent_repaint("\x1b[H"));
+        assert!(!is_idempotent_repaint("a"));
+        assert!(!is_idempotent_repaint("\n"));
+        assert!(!is_idempotent_repaint("\r|"));
+        assert!(!is_idempotent_repaint("\r\x1b[Kline\r\n"));
+        assert!(!is_idempotent_repaint("\x1b[5A"));
+    }
+
+    /// Serve the full router on an ephemeral port for tests that need real WebSockets.
+    async fn spawn_test_server(state: Arc<AppState>) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, build_router(state)).await.ok();
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_readonly_terminal_subscriber_cannot_write() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        let addr = spawn_test_server(state.clone()).await;
+        let url = format!("ws://{}/sessions/{}/ws?readonly=true", addr, session_id);
+        let (mut observer, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+
+        observer
+            .send(WsMessage::Text("echo ro$((1 + 1))\n".to_string()))
+            .await
+            .unwrap();
+        // Frames are handled in order, so the pong means the input frame was processed
+        observer.send(WsMessage::Ping(Vec::new())).await.unwrap();
+        let mut output = String::new();
+        let deadline = tokio::time::Duration::from_secs(10);
+        tokio::time::timeout(deadline, async {
+            loop {
+                match observer.next().await {
+                    Some(Ok(WsMessage::Pong(_))) => break,
+                    Some(Ok(WsMessage::Binary(data))) => {
+                        output.push_str(&String::from_utf8_lossy(&data))
+                    }
+                    Some(Ok(_)) => {}
+                    other => panic!("socket ended: {:?}", other),
+                }
+            }
+        })
+        .await
+        .expect("no pong");
+
+        session.write_input("echo rw$((2 + 1))\n").unwrap();
+        tokio::time::timeout(deadline, async {
+            while !output.contains("rw3") {
+                match observer.next().await {
+                    Some(Ok(WsMessage::Binary(data))) => {
+                        output.push_str(&String::from_utf8_lossy(&data))
+                    }
+                    Some(Ok(_)) => {}
+                    other => panic!("socket ended: {:?}", other),
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("observer saw no output: {:?}", output));
+        assert!(
+            !output.contains("ro$((") && !output.contains("ro2"),
+            "read-only input reached the PTY: {:?}",
+            output
+        );
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_process_endpoint_drives_terminal_when_enabled() {
+        let mut state = AppState::new();
+        state.enable_test_endpoints = true;
+        let state = Arc::new(state);
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        // No reader task: only the injected bytes reach the emulator
+        let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+
+        let post = |state: Arc<AppState>| {
+            build_router(state).oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(format!("/sessions/{}/process", session_id))
+                    .body(Body::from("\x1b[1;31mhello\x1b[0m world"))
+                    .unwrap(),
+            )
+        };
+        let response = post(state.clone()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = build_router(state.clone())
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/sessions/{}/capture?processed=true", session_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let capture: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(capture["content"]
+            .as_str()
+            .unwrap()
+            .starts_with("hello world"));
+        {
+            let terminal = session.terminal.lock();
+            let styled = terminal.get_cell(0, 0).style;
+            assert_eq!(styled.fg, Some(cmux_terminal::Color::Red));
+            assert!(styled.add_modifier.contains(cmux_terminal::Modifier::BOLD));
+            assert_eq!(terminal.get_cell(0, 6).style.fg, None);
+        }
+
+        // Without the flag the route does not exist
+        let mut disabled = AppState::new();
+        disabled.sessions = RwLock::new(state.sessions.read().clone());
+        let response = post(Arc::new(disabled)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_capture_tail_returns_last_lines() {
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        // No reader task: only the injected bytes reach the emulator
+        let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        let output: String = (0..100).map(|i| format!("line {}\r\n", i)).collect();
+        session.process_terminal_bytes(output.as_bytes());
+
+        let capture = |query: &'static str| {
+            let app = build_router(state.clone());
+            let uri = format!("/sessions/{}/capture?{}", session_id, query);
+            async move {
+                let response = app
+                    .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).ok(),
+                )
+            }
+        };
+        let (status, body) = capture("processed=true&tail=3").await;
+        assert_eq!(status, StatusCode::OK);
+        let body = body.unwrap();
+        assert_eq!(body["content"], "line 98\nline 99\n");
+        assert_eq!(body["lines"], 3);
+
+        let (status, _) = capture("processed=true&tail=lots").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_large_scrollback_is_split_into_small_frames() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let mut state = AppState::new();
+        state.max_ws_frame_bytes = 64;
+        let state = Arc::new(state);
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        // No reader task, so the scrollback is the only thing sent
+        let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        let scrollback: String = (0..100).map(|i| format!("line {i}\r\n")).collect();
+        *session.scrollback.write() = scrollback.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+
+        let addr = spawn_test_server(state.clone()).await;
+        let url = format!("ws://{}/sessions/{}/ws", addr, session_id);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        let mut frames = 0;
+        while received.len() < scrollback.len() {
+            let msg = tokio::time::timeout(tokio::time::Duration::from_secs(5), ws.next())
+                .await
+                .expect("timed out waiting for scrollback");
+            match msg {
+                Some(Ok(WsMessage::Binary(data))) => {
+                    assert!(data.len() <= 64, "frame of {} bytes", data.len());
+                    frames += 1;
+                    received.extend_from_slice(&data);
+                }
+                Some(Ok(_)) => {}
+                other => panic!("socket ended: {:?}", other),
+            }
+        }
+        assert_eq!(received, scrollback.as_bytes());
+        assert!(frames > 1);
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_session_deleted_before_upgrade_gets_close_frame() {
+        use axum::{extract::Request, middleware::Next};
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, _reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+
+        // Delete the session once the attach has passed its existence check, before the
+        // socket is upgraded
+        let app = build_router(state.clone()).layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            |State(state): State<Arc<AppState>>, req: Request, next: Next| async move {
+                let response = next.run(req).await;
+                state.sessions.write().clear();
+                response
+            },
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.ok();
+        });
+
+        let url = format!("ws://{}/sessions/{}/ws", addr, session_id);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        let msg = tokio::time::timeout(tokio::time::Duration::from_secs(5), ws.next())
+            .await
+            .expect("timed out waiting for close frame");
+        match msg {
+            Some(Ok(WsMessage::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), WS_CLOSE_SESSION_NOT_FOUND);
+                assert_eq!(frame.reason, "session not found");
+            }
+            other => panic!("expected a close frame, got {:?}", other),
+        }
+
+        session.kill();
+    }
+
+    /// Test base64 subprotocol negotiation on the terminal WebSocket
+    #[tokio::test]
+    async fn test_terminal_websocket_base64_subprotocol() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as WsMessage};
+
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        let addr = spawn_test_server(state.clone()).await;
+        let mut ws_request = format!("ws://{}/sessions/{}/ws", addr, session_id)
+            .into_client_request()
+            .unwrap();
+        ws_request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", "base64".parse().unwrap());
+        let (mut ws, response) = tokio_tungstenite::connect_async(ws_request).await.unwrap();
+        assert_eq!(
+            response.headers().get("sec-websocket-protocol").unwrap(),
+            "base64"
+        );
+
+        ws.send(WsMessage::Text(BASE64.encode("echo b64-$((40 + 2))\n")))
+            .await
+            .unwrap();
+
+        let mut output = Vec::new();
+        let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(5);
+        while !String::from_utf8_lossy(&output).contains("b64-42") {
+            let msg = tokio::time::timeout_at(deadline, ws.next())
+                .await
+                .expect("timed out waiting for output")
+                .unwrap()
+                .unwrap();
+            match msg {
+                WsMessage::Text(text) => output.extend(BASE64.decode(text).unwrap()),
+                other => panic!("expected base64 text frame, got {:?}", other),
+            }
+        }
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_queue_collapses_into_resync() {
+        let queue = SubscriberQueue::new(2);
+        assert!(queue.push("a".to_string()));
+        assert!(queue.push("b".to_string()));
+        assert!(!queue.push("c".to_string()));
+
+        queue.resync("snapshot".to_string());
+        queue.close();
+        assert_eq!(queue.pop().await.as_deref(), Some("snapshot"));
+        assert_eq!(queue.pop().await, None);
+    }
+
+    /// A terminal socket that stops reading must not hold up the others
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_slow_terminal_subscriber_is_resynced() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let mut state = AppState::new();
+        state.subscriber_queue_limit = 4;
+        let state = Arc::new(state);
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        let addr = spawn_test_server(state.clone()).await;
+        let url = format!("ws://{}/sessions/{}/ws", addr, session_id);
+        let (mut fast, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        let (mut slow, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+
+        // Enough output to fill the slow client's socket buffers many times over
+        fast.send(WsMessage::Text(
+            "head -c 8000000 /dev/zero | tr '\\0' x; echo; echo end$((1 + 1))\n".to_string(),
+        ))
+        .await
+        .unwrap();
+
+        async fn read_until(
+            ws: &mut tokio_tungstenite::WebSocketStream<
+                tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+            >,
+            marker: &str,
+        ) -> bool {
+            let mut saw_reset = false;
+            let mut tail = String::new();
+            while !tail.contains(marker) {
+                match ws.next().await {
+                    Some(Ok(WsMessage::Binary(data))) => {
+                        let text = String::from_utf8_lossy(&data);
+                        saw_reset |= text.contains("\x1bc");
+                        tail.push_str(&text);
+                        // Only the last few characters matter for spotting the marker
+                        let cut = tail.char_indices().rev().nth(31).map_or(0, |(i, _)| i);
+                        tail.drain(..cut);
+                    }
+                    Some(Ok(_)) => {}
+                    other => panic!("socket ended: {:?}", other),
+                }
+            }
+            saw_reset
+        }
+
+        let timeout = tokio::time::Duration::from_secs(60);
+        tokio::time::timeout(timeout, read_until(&mut fast, "end2"))
+            .await
+            .expect("fast subscriber stalled");
+        let resynced = tokio::time::timeout(timeout, read_until(&mut slow, "end2"))
+            .await
+            .expect("slow subscriber never caught up");
+        assert!(resynced, "slow subscriber should have been resynced");
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_resync_request_repaints_current_screen() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let state = Arc::new(AppState::new());
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        // Enter the alternate screen, then push that setup out of the byte scrollback
+        session
+            .write_input(&format!(
+                "printf '\\033[?1049h\\033[1;31m'; head -c {} /dev/zero | tr '\\0' x; \
+                 printf 'snap%s\\n' 42\n",
+                MAX_SCROLLBACK + 10_000
+            ))
+            .unwrap();
+        let deadline = tokio::time::Duration::from_secs(10);
+        tokio::time::timeout(deadline, async {
+            while !session.get_terminal_viewport().0.contains("snap42") {
+                tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("command produced no output");
+
+        // Read-only subscribers may ask for a resync too
+        let addr = spawn_test_server(state.clone()).await;
+        let url = format!("ws://{}/sessions/{}/ws?readonly=true", addr, session_id);
+        let (mut ws, _) = tokio_tungstenite::connect_async(url.as_str())
+            .await
+            .unwrap();
+        ws.send(WsMessage::Text(r#"{"type":"resync"}"#.to_string()))
+            .await
+            .unwrap();
+
+        assert!(session.terminal.lock().is_alternate_screen());
+        let mut received = String::new();
+        tokio::time::timeout(deadline, async {
+            loop {
+                match ws.next().await {
+                    Some(Ok(WsMessage::Binary(data))) => {
+                        received.push_str(&String::from_utf8_lossy(&data))
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("socket ended: {:?}", other),
+                }
+                // Replaying the snapshot after the reset must rebuild the same screen
+                if let Some(start) = received.rfind("\x1bc") {
+                    let terminal = session.terminal.lock().clone();
+                    let mut replay = VirtualTerminal::new(terminal.rows(), terminal.cols());
+                    replay.process(&received.as_bytes()[start..]);
+                    if replay.get_lines() == terminal.get_lines()
+                        && replay.is_alternate_screen()
+                        && replay.get_cell(0, 0).style == terminal.get_cell(0, 0).style
+                    {
+                        break;
+                    }
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("no resync snapshot in {:?}", received));
+
+        session.kill();
+    }
+
+    #[tokio::test]
+    async fn test_input_broadcast_is_opt_in() {
+        let state = Arc::new(AppState::new());
+        let mut events = state.event_tx.subscribe();
+        let quiet = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let shared = CreateSessionRequest {
+            metadata: Some(serde_json::json!({"broadcast_input": true})),
+            ..quiet.clone()
+        };
+        let (quiet, _quiet_reader) = create_pty_session_inner(&state, &quiet).unwrap();
+        let (shared, _shared_reader) = create_pty_session_inner(&state, &shared).unwrap();
+
+        quiet.write_input("secret\n").unwrap();
+        shared.write_input("ls\n").unwrap();
+
+        match events.try_recv() {
+            Ok(ServerEvent::Input { pty_id, data }) => {
+                assert_eq!(pty_id, shared.id);
+                assert_eq!(data, "ls\n");
+            }
+            other => panic!("expected a single input event, got {:?}", other),
+        }
+        assert!(events.try_recv().is_err());
+
+        quiet.kill();
+        shared.kill();
+    }
+
+    #[tokio::test]
+    async fn test_events_websocket_heartbeat_when_idle() {
+        use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+        let mut state = AppState::new();
+        state.heartbeat_interval = Some(tokio::time::Duration::from_millis(100));
+        let addr = spawn_test_server(Arc::new(state)).await;
+        let (mut ws, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", addr))
+            .await
+            .unwrap();
+
+        let mut types = Vec::new();
+        for _ in 0..2 {
+            let msg = tokio::time::timeout(tokio::time::Duration::from_secs(2), ws.next())
+                .await
+                .expect("timed out waiting for event")
+                .unwrap()
+                .unwrap();
+            let WsMessage::Text(text) = msg else {
+                panic!("expected text frame, got {:?}", msg);
+            };
+            let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+            types.push(value["type"].as_str().unwrap().to_string());
+        }
+        assert_eq!(types, ["state_sync", "heartbeat"]);
+    }
+
+    /// Test that lingering sessions keep their output after the process exits
+    #[tokio::test]
+    async fn test_linger_after_exit_keeps_capture() {
+        let state = Arc::new(AppState::new());
+
+        let request = CreateSessionRequest {
+            shell: "/bin/sh".to_string(),
+            cwd: "/tmp".to_string(),
+            linger_after_exit: true,
+            ..Default::default()
+        };
+
+        let (session, reader) = create_pty_session_inner(&state, &request).unwrap();
+        let session_id = session.id.clone();
+        state
+            .sessions
+            .write()
+            .insert(session_id.clone(), session.clone());
+        let reader_task = tokio::spawn(spawn_pty_reader(session.clone(), reader, state.clone()));
+
+        // printf keeps the expected output distinct from the echoed command line
+        session.write_input("printf 'h%s\\n' i; exit\n").unwrap();
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), reader_task)
+            .await
+            .expect("process did not exit")
+            .unwrap();
+
+        assert!(!session.is_alive());
+        let listed = state.get_ordered_sessions();
+        assert_eq!(listed.len(), 1);
+        assert!(!listed[0].alive);
+
+        let app = Router::new()
+            .route("/sessions/:session_id/capture", get(capture_session))
+            .with_state(state.clone());
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/sessions/{}/capture?processed=true", session_id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let content = json["content"].as_str().unwrap();
+        assert!(
+            content.lines().any(|line| line.trim_end().ends_with("hi")),
+            "unexpected capture: {:?}",
+            content
+        );
+    }
 }
@@ -6,6 +6,7 @@
 //! - Row structure with canonical line tracking for proper resize/rewrap
 
 use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::VecDeque;
 use std::sync::Arc;
@@ -61,11 +62,15 @@ impl SharedStyles {
 }
 
 /// Character styles - similar to ratatui's Style but designed for sharing.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub struct CharacterStyles {
     pub foreground: Option<Color>,
     pub background: Option<Color>,
     pub modifiers: Modifier,
+    /// Underline color (SGR 58); `None` means the foreground color is used.
+    pub underline_color: Option<Color>,
+    /// OSC 8 hyperlink, as an id into the owning terminal's hyperlink table.
+    pub hyperlink: Option<u32>,
 }
 
 impl CharacterStyles {
@@ -75,6 +80,8 @@ impl CharacterStyles {
             foreground: style.fg,
             background: style.bg,
             modifiers: style.add_modifier,
+            underline_color: None,
+            hyperlink: None,
         }
     }
 
@@ -20,6 +20,29 @@ pub struct DaFilter {
     buffer: Vec<u8>,
     /// Current parsing state
     state: DaFilterState,
+    /// Running totals of what has been filtered
+    stats: FilterStats,
+}
+
+/// Counts of the sequences a [`DaFilter`] has removed. Snapshot with
+/// [`DaFilter::stats`] and diff two snapshots to get a rate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilterStats {
+    /// DA1 queries (`ESC [ c`, `ESC [ 0 c`)
+    pub da1_queries_filtered: u64,
+    /// DA2 queries (`ESC [ > c`, `ESC [ > 0 c`)
+    pub da2_queries_filtered: u64,
+    /// DA1 and DA2 responses (`ESC [ ? ... c`, `ESC [ > ... c`)
+    pub responses_filtered: u64,
+    /// Total bytes of all filtered sequences
+    pub bytes_dropped: u64,
+}
+
+/// Kind of sequence being dropped, for [`FilterStats`].
+enum Filtered {
+    Da1Query,
+    Da2Query,
+    Response,
 }
 
 #[derive(Default, Clone, Copy, PartialEq)]
@@ -43,6 +66,24 @@ impl DaFilter {
         Self::default()
     }
 
+    /// Totals of the sequences filtered so far.
+    pub fn stats(&self) -> FilterStats {
+        self.stats
+    }
+
+    /// Drop the buffered sequence, whose final byte was just read, and count it.
+    fn discard(&mut self, kind: Filtered) {
+        let counter = match kind {
+            Filtered::Da1Query => &mut self.stats.da1_queries_filtered,
+            Filtered::Da2Query => &mut self.stats.da2_queries_filtered,
+            Filtered::Response => &mut self.stats.responses_filtered,
+        };
+        *counter += 1;
+        self.stats.bytes_dropped += self.buffer.len() as u64 + 1;
+        self.buffer.clear();
+        self.state = DaFilterState::Normal;
+    }
+
     /// Process a chunk of data, returning filtered output.
     /// Call this for each chunk of PTY output.
     pub fn filter(&mut self, data: &[u8]) -> Vec<u8> {
@@ -80,9 +121,10 @@ impl DaFilter {
                         b'>' => self.state = DaFilterState::CsiGreater,
                         b'0' => self.state = DaFilterState::InParams,
                         b'c' => {
-                            // DA1 query: ESC [ c - filter it out
-                            self.buffer.clear();
-                            self.state = DaFilterState::Normal;
+                            // DA1 query: ESC [ c - filter it out. The buffer already
+                            // holds the 'c', so don't count it twice.
+                            self.buffer.pop();
+                            self.discard(Filtered::Da1Query);
                         }
                         // Any other character means it's not a DA sequence
                         _ => {
@@ -96,8 +138,7 @@ impl DaFilter {
                 DaFilterState::CsiQuestion => {
                     if byte == b'c' {
                         // DA1 response: ESC [ ? params c - filter it out
-                        self.buffer.clear();
-                        self.state = DaFilterState::Normal;
+                        self.discard(Filtered::Response);
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params
                         self.buffer.push(byte);
@@ -113,9 +154,13 @@ impl DaFilter {
 
                 DaFilterState::CsiGreater => {
                     if byte == b'c' {
-                        // DA2 query/response: ESC [ > c or ESC [ > params c - filter it out
-                        self.buffer.clear();
-                        self.state = DaFilterState::Normal;
+                        // DA2 query/response: ESC [ > c or ESC [ > params c - filter it out.
+                        // Queries carry no parameter other than an optional 0.
+                        let kind = match &self.buffer[3..] {
+                            b"" | b"0" => Filtered::Da2Query,
+                            _ => Filtered::Response,
+                        };
+                        self.discard(kind);
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params (DA2 response)
                         self.buffer.push(byte);
@@ -131,8 +176,7 @@ impl DaFilter {
                 DaFilterState::InParams => {
                     if byte == b'c' {
                         // DA1 query with param: ESC [ 0 c - filter it out
-                        self.buffer.clear();
-                        self.state = DaFilterState::Normal;
+                        self.discard(Filtered::Da1Query);
                     } else if byte.is_ascii_digit() || byte == b';' {
                         // Continue accumulating params
                         self.buffer.push(byte);
@@ -263,4 +307,27 @@ mod tests {
         let result = filter_da_queries(b"Before\x1b[cAfter");
         assert_eq!(result, b"BeforeAfter");
     }
+
+    #[test]
+    fn test_stats_count_filtered_sequences() {
+        let mut filter = DaFilter::new();
+        assert_eq!(filter.stats(), FilterStats::default());
+
+        filter.filter(b"a\x1b[c b\x1b[0c\x1b[>c\x1b[?25h\x1b[?64;1");
+        filter.filter(b"c\x1b[>1;123;0c");
+        let stats = filter.stats();
+        assert_eq!(
+            stats,
+            FilterStats {
+                da1_queries_filtered: 2,
+                da2_queries_filtered: 1,
+                responses_filtered: 2,
+                bytes_dropped: (3 + 4 + 4 + 8 + 11) as u64,
+            }
+        );
+
+        // Snapshots can be diffed; unfiltered output leaves them unchanged
+        filter.filter(b"plain text\x1b[?25l");
+        assert_eq!(filter.stats(), stats);
+    }
 }
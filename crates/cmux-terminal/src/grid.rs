@@ -8,11 +8,12 @@
 //! This design enables efficient scrolling without reallocating large buffers.
 
 use std::collections::{HashSet, VecDeque};
+use std::ops::RangeInclusive;
 
 use crate::character::{CharacterStyles, Row, SharedStyles, TerminalCharacter};
 
-/// Maximum number of lines to keep in scrollback.
-const MAX_SCROLLBACK_LINES: usize = 10_000;
+/// Default maximum number of lines to keep in scrollback.
+pub const DEFAULT_MAX_SCROLLBACK: usize = 10_000;
 
 /// Terminal grid with tripartite design for efficient scrolling.
 #[derive(Clone, Debug)]
@@ -41,15 +42,23 @@ pub struct Grid {
     pub left_margin: usize,
     /// Right margin (0-indexed, inclusive) for DECSLRM.
     pub right_margin: usize,
-    /// Set of line indices that have changed since last render.
-    pub changed_lines: HashSet<usize>,
+    /// Per-row dirty flags for the viewport, set by every edit since the last
+    /// [`Grid::clear_dirty`].
+    dirty: Vec<bool>,
     /// Flag to indicate full redraw is needed.
     pub needs_full_redraw: bool,
+    /// Maximum number of lines kept in `lines_above`.
+    max_scrollback: usize,
 }
 
 impl Grid {
     /// Create a new grid with the given dimensions.
     pub fn new(rows: usize, cols: usize) -> Self {
+        Self::with_scrollback(rows, cols, DEFAULT_MAX_SCROLLBACK)
+    }
+
+    /// Create a new grid keeping at most `max_scrollback` lines of scrollback.
+    pub fn with_scrollback(rows: usize, cols: usize, max_scrollback: usize) -> Self {
         let viewport: Vec<Row> = (0..rows).map(|_| Row::filled(cols)).collect();
 
         Self {
@@ -65,37 +74,78 @@ impl Grid {
             scroll_region: (0, rows.saturating_sub(1)),
             left_margin: 0,
             right_margin: cols.saturating_sub(1),
-            changed_lines: HashSet::new(),
+            dirty: vec![true; rows],
             needs_full_redraw: true,
+            max_scrollback,
+        }
+    }
+
+    /// Maximum number of lines kept in scrollback.
+    pub fn max_scrollback(&self) -> usize {
+        self.max_scrollback
+    }
+
+    /// Change the scrollback cap. Lowering it drops the oldest lines right away and
+    /// releases the memory they used.
+    pub fn set_max_scrollback(&mut self, max_scrollback: usize) {
+        self.max_scrollback = max_scrollback;
+        self.trim_scrollback();
+    }
+
+    /// Drop the oldest scrollback lines beyond the cap.
+    pub(crate) fn trim_scrollback(&mut self) {
+        let excess = self.lines_above.len().saturating_sub(self.max_scrollback);
+        if excess > 0 {
+            self.lines_above.drain(..excess);
+            self.lines_above.shrink_to_fit();
         }
     }
 
     /// Mark a line as changed for differential rendering.
     #[inline]
     pub fn mark_line_changed(&mut self, line: usize) {
-        if line < self.rows {
-            self.changed_lines.insert(line);
+        if let Some(dirty) = self.dirty.get_mut(line) {
+            *dirty = true;
         }
     }
 
     /// Mark all lines as changed.
     pub fn mark_all_changed(&mut self) {
         self.needs_full_redraw = true;
-        self.changed_lines.clear();
-        for i in 0..self.rows {
-            self.changed_lines.insert(i);
-        }
+        self.dirty.clear();
+        self.dirty.resize(self.rows, true);
+    }
+
+    /// Forget all dirty rows, typically once a frame has been drawn.
+    pub fn clear_dirty(&mut self) {
+        self.dirty.fill(false);
+        self.needs_full_redraw = false;
+    }
+
+    /// Viewport row indices touched since the last [`Grid::clear_dirty`], in ascending order.
+    pub fn dirty_rows(&self) -> Vec<usize> {
+        self.dirty
+            .iter()
+            .enumerate()
+            .filter_map(|(row, &dirty)| dirty.then_some(row))
+            .collect()
+    }
+
+    /// Whether a single viewport row was touched since the last [`Grid::clear_dirty`].
+    pub fn is_row_dirty(&self, row: usize) -> bool {
+        self.dirty.get(row).copied().unwrap_or(false)
     }
 
     /// Clear the changed lines set.
+    #[deprecated(note = "use `Grid::clear_dirty`")]
     pub fn clear_changed(&mut self) {
-        self.changed_lines.clear();
-        self.needs_full_redraw = false;
+        self.clear_dirty();
     }
 
     /// Get the changed line indices.
-    pub fn get_changed_lines(&self) -> &HashSet<usize> {
-        &self.changed_lines
+    #[deprecated(note = "use `Grid::dirty_rows` or `Grid::is_row_dirty`")]
+    pub fn get_changed_lines(&self) -> HashSet<usize> {
+        self.dirty_rows().into_iter().collect()
     }
 
     /// Check if full redraw is needed.
@@ -181,16 +231,23 @@ impl Grid {
     pub fn newline(&mut self) {
         let (_top, bottom) = self.scroll_region;
 
-        if self.cursor_row >= bottom {
+        // Only the bottom margin scrolls; below the region the cursor moves down and
+        // stops at the last row without scrolling anything
+        if self.cursor_row == bottom {
             self.scroll_up_in_region(1);
-        } else {
+        } else if self.cursor_row + 1 < self.rows {
             self.cursor_row += 1;
         }
     }
 
-    /// Scroll up within the scroll region.
+    /// Scroll up within the scroll region. With left/right margins set only the columns
+    /// between them move, and nothing goes to scrollback.
     pub fn scroll_up_in_region(&mut self, count: usize) {
         let (top, bottom) = self.scroll_region;
+        if self.has_horizontal_margins() {
+            self.shift_rows_within_margins(count, top, bottom, true);
+            return;
+        }
 
         for _ in 0..count {
             if top == 0 {
@@ -217,9 +274,13 @@ impl Grid {
         }
     }
 
-    /// Scroll down within the scroll region.
+    /// Scroll down within the scroll region, between the left/right margins if set.
     pub fn scroll_down_in_region(&mut self, count: usize) {
         let (top, bottom) = self.scroll_region;
+        if self.has_horizontal_margins() {
+            self.shift_rows_within_margins(count, top, bottom, false);
+            return;
+        }
 
         for _ in 0..count {
             if top < self.viewport.len() && bottom < self.viewport.len() && top <= bottom {
@@ -234,9 +295,111 @@ impl Grid {
         }
     }
 
+    /// Shift columns `left..=right` of every scroll-region row left by `count` (SL),
+    /// filling the vacated columns at the right edge with blanks in the current style.
+    pub fn scroll_left_in_region(&mut self, count: usize, left: usize, right: usize) {
+        let (top, bottom) = self.scroll_region;
+        self.shift_columns(top..=bottom, count, left, right, true);
+    }
+
+    /// Shift columns `left..=right` of every scroll-region row right by `count` (SR),
+    /// filling the vacated columns at the left edge with blanks in the current style.
+    pub fn scroll_right_in_region(&mut self, count: usize, left: usize, right: usize) {
+        let (top, bottom) = self.scroll_region;
+        self.shift_columns(top..=bottom, count, left, right, false);
+    }
+
+    /// Shift columns `left..=right` of each row in `rows` left (or right) by `count`,
+    /// filling the vacated columns with blanks in the current style.
+    fn shift_columns(
+        &mut self,
+        rows: RangeInclusive<usize>,
+        count: usize,
+        left: usize,
+        right: usize,
+        leftward: bool,
+    ) {
+        let right = right.min(self.cols.saturating_sub(1));
+        if left > right || count == 0 {
+            return;
+        }
+        let width = right - left + 1;
+        let count = count.min(width);
+        let blank = TerminalCharacter::blank_with_style(self.current_shared_styles.clone());
+        let (top, bottom) = rows.into_inner();
+
+        for row in top..=bottom.min(self.viewport.len().saturating_sub(1)) {
+            let line = &mut self.viewport[row];
+            let mut cells: Vec<TerminalCharacter> = (left..=right)
+                .map(|col| line.get(col).cloned().unwrap_or_default())
+                .collect();
+            if leftward {
+                cells.drain(..count);
+                cells.extend(std::iter::repeat_n(blank.clone(), count));
+            } else {
+                cells.truncate(width - count);
+                cells.splice(0..0, std::iter::repeat_n(blank.clone(), count));
+            }
+            for (offset, cell) in cells.into_iter().enumerate() {
+                line.set(left + offset, cell);
+            }
+            self.mark_line_changed(row);
+        }
+    }
+
+    /// Whether left/right margins (DECSLRM) narrower than the screen are set.
+    fn has_horizontal_margins(&self) -> bool {
+        self.left_margin > 0 || self.right_margin + 1 < self.cols
+    }
+
+    /// Whether the cursor is between the left and right margins.
+    pub fn cursor_within_horizontal_margins(&self) -> bool {
+        (self.left_margin..=self.right_margin).contains(&self.cursor_col)
+    }
+
+    /// Move the part of rows `top..=bottom` between the left and right margins up (or
+    /// down) by `count` rows, blanking what is uncovered. Columns outside the margins
+    /// keep their contents.
+    fn shift_rows_within_margins(&mut self, count: usize, top: usize, bottom: usize, up: bool) {
+        let bottom = bottom.min(self.viewport.len().saturating_sub(1));
+        let right = self.right_margin.min(self.cols.saturating_sub(1));
+        let left = self.left_margin;
+        if top > bottom || left > right || count == 0 {
+            return;
+        }
+        let height = bottom - top + 1;
+        let count = count.min(height);
+        let blank = vec![TerminalCharacter::default(); right - left + 1];
+
+        let mut segments: Vec<Vec<TerminalCharacter>> = self.viewport[top..=bottom]
+            .iter()
+            .map(|line| {
+                (left..=right)
+                    .map(|col| line.get(col).cloned().unwrap_or_default())
+                    .collect()
+            })
+            .collect();
+        if up {
+            segments.drain(..count);
+            segments.extend(std::iter::repeat_n(blank, count));
+        } else {
+            segments.truncate(height - count);
+            segments.splice(0..0, std::iter::repeat_n(blank, count));
+        }
+        for (offset, segment) in segments.into_iter().enumerate() {
+            let line = &mut self.viewport[top + offset];
+            for (col, cell) in segment.into_iter().enumerate() {
+                line.set(left + col, cell);
+            }
+            self.mark_line_changed(top + offset);
+        }
+    }
+
     /// Insert n blank lines at the current cursor row (IL / CSI L).
     /// Lines at and below cursor shift down within scroll region.
     /// Lines that fall off the bottom of the scroll region are lost.
+    /// With left/right margins set, only the columns between them move, and nothing
+    /// happens when the cursor is outside them.
     pub fn insert_lines_at_cursor(&mut self, count: usize) {
         let (top, bottom) = self.scroll_region;
         let cursor_row = self.cursor_row;
@@ -245,6 +408,14 @@ impl Grid {
         if cursor_row < top || cursor_row > bottom {
             return;
         }
+        if self.has_horizontal_margins() {
+            if self.cursor_within_horizontal_margins() {
+                self.shift_rows_within_margins(count, cursor_row, bottom, false);
+            }
+            return;
+        }
+        // Shifting by more than the remaining region height is a no-op beyond clearing it
+        let count = count.min(bottom + 1 - cursor_row);
 
         for _ in 0..count {
             // Remove line at bottom of scroll region (it falls off)
@@ -266,6 +437,7 @@ impl Grid {
     /// Delete n lines at the current cursor row (DL / CSI M).
     /// Lines below cursor shift up within scroll region.
     /// Blank lines are inserted at the bottom of the scroll region.
+    /// Left/right margins restrict it like IL.
     pub fn delete_lines_at_cursor(&mut self, count: usize) {
         let (top, bottom) = self.scroll_region;
         let cursor_row = self.cursor_row;
@@ -274,6 +446,14 @@ impl Grid {
         if cursor_row < top || cursor_row > bottom {
             return;
         }
+        if self.has_horizontal_margins() {
+            if self.cursor_within_horizontal_margins() {
+                self.shift_rows_within_margins(count, cursor_row, bottom, true);
+            }
+            return;
+        }
+        // Shifting by more than the remaining region height is a no-op beyond clearing it
+        let count = count.min(bottom + 1 - cursor_row);
 
         for _ in 0..count {
             // Remove line at cursor row
@@ -293,7 +473,10 @@ impl Grid {
 
     /// Push a line to the scrollback buffer, respecting the maximum size.
     fn push_to_scrollback(&mut self, line: Row) {
-        if self.lines_above.len() >= MAX_SCROLLBACK_LINES {
+        if self.max_scrollback == 0 {
+            return;
+        }
+        if self.lines_above.len() >= self.max_scrollback {
             self.lines_above.pop_front();
         }
         self.lines_above.push_back(line);
@@ -362,8 +545,16 @@ impl Grid {
         }
     }
 
-    /// Insert blank characters at cursor position.
+    /// Insert blank characters at cursor position. With left/right margins set, characters
+    /// shift only up to the right margin, and nothing happens outside the margins.
     pub fn insert_chars(&mut self, count: usize) {
+        if self.has_horizontal_margins() {
+            if self.cursor_within_horizontal_margins() {
+                let row = self.cursor_row;
+                self.shift_columns(row..=row, count, self.cursor_col, self.right_margin, false);
+            }
+            return;
+        }
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
             let style = self.current_shared_styles.clone();
@@ -376,8 +567,16 @@ impl Grid {
         }
     }
 
-    /// Delete characters at cursor position.
+    /// Delete characters at cursor position, pulling in blanks at the right margin when
+    /// left/right margins are set (and doing nothing outside them).
     pub fn delete_chars(&mut self, count: usize) {
+        if self.has_horizontal_margins() {
+            if self.cursor_within_horizontal_margins() {
+                let row = self.cursor_row;
+                self.shift_columns(row..=row, count, self.cursor_col, self.right_margin, true);
+            }
+            return;
+        }
         if self.cursor_row < self.viewport.len() {
             self.mark_line_changed(self.cursor_row);
             let style = self.current_shared_styles.clone();
@@ -404,7 +603,8 @@ impl Grid {
         }
     }
 
-    /// Resize the grid to new dimensions.
+    /// Resize the grid to new dimensions. Lines are padded or cut to the new width; see
+    /// [`resize_reflow`](Self::resize_reflow) to re-wrap them instead.
     pub fn resize(&mut self, new_rows: usize, new_cols: usize) {
         if new_rows == self.rows && new_cols == self.cols {
             return;
@@ -417,8 +617,7 @@ impl Grid {
 
         // Handle width change
         if new_cols != old_cols {
-            // Rewrap lines if width changed
-            self.rewrap_lines(new_cols);
+            self.fit_lines_to_width(new_cols);
         }
 
         // Handle height change
@@ -432,8 +631,11 @@ impl Grid {
             }
         }
 
-        // Adjust scroll region
+        // Reset scroll region and left/right margins to the full screen (as xterm does), so
+        // neither can reference rows or columns that no longer exist
         self.scroll_region = (0, new_rows.saturating_sub(1));
+        self.left_margin = 0;
+        self.right_margin = new_cols.saturating_sub(1);
 
         // Clamp cursor to new bounds
         self.cursor_row = self.cursor_row.min(new_rows.saturating_sub(1));
@@ -445,44 +647,131 @@ impl Grid {
         self.mark_all_changed();
     }
 
-    /// Rewrap lines when the terminal width changes.
-    fn rewrap_lines(&mut self, new_cols: usize) {
-        // Rewrap viewport lines
-        let mut new_viewport = Vec::new();
-        for row in &self.viewport {
-            if new_cols < self.cols {
-                // Narrower: split long lines
-                let split_rows = row.split_to_rows_of_length(new_cols);
-                for mut split_row in split_rows {
-                    split_row.truncate(new_cols);
-                    split_row.fill_to_width(new_cols);
-                    new_viewport.push(split_row);
-                }
-            } else {
-                // Wider: just extend/truncate
-                let mut new_row = row.clone();
-                new_row.fill_to_width(new_cols);
-                new_row.truncate(new_cols);
-                new_viewport.push(new_row);
+    /// Pad or cut every line to a new width. Content past the new width is lost.
+    fn fit_lines_to_width(&mut self, new_cols: usize) {
+        for row in self
+            .viewport
+            .iter_mut()
+            .chain(self.lines_above.iter_mut())
+            .chain(self.lines_below.iter_mut())
+        {
+            row.fill_to_width(new_cols);
+            row.truncate(new_cols);
+        }
+    }
+
+    /// Resize the grid, re-wrapping lines to the new width instead of cutting them.
+    ///
+    /// Rows continued by a soft wrap (`is_canonical == false`) are joined with the row
+    /// before them into one logical line, which is then split again at `new_cols`. So
+    /// narrowing wraps long lines onto more rows and widening joins the pieces back,
+    /// while lines ended by a newline stay separate. Rows pushed off the top go to
+    /// scrollback, and the cursor stays on the same character. With an unchanged width
+    /// this is the same as [`resize`](Self::resize).
+    pub fn resize_reflow(&mut self, new_rows: usize, new_cols: usize) {
+        if new_cols == self.cols || new_rows == 0 || new_cols == 0 {
+            self.resize(new_rows, new_cols);
+            return;
+        }
+
+        let old_cols = self.cols;
+        let cursor_line = self.lines_above.len() + self.cursor_row;
+        let rows: Vec<Row> = std::mem::take(&mut self.lines_above)
+            .into_iter()
+            .chain(std::mem::take(&mut self.viewport))
+            .chain(std::mem::take(&mut self.lines_below))
+            .collect();
+
+        // Join soft-wrapped rows into logical lines, noting where the cursor falls
+        let mut logical: Vec<Vec<TerminalCharacter>> = Vec::new();
+        let mut cursor = (0, 0);
+        for (index, row) in rows.into_iter().enumerate() {
+            if row.is_canonical || logical.is_empty() {
+                logical.push(Vec::new());
             }
+            let line_index = logical.len() - 1;
+            let line = &mut logical[line_index];
+            if index == cursor_line {
+                cursor = (line_index, line.len() + self.cursor_col.min(old_cols - 1));
+            }
+            let mut cells: Vec<TerminalCharacter> = row.columns.into_iter().collect();
+            cells.resize(old_cols, TerminalCharacter::default());
+            line.extend(cells);
         }
-        self.viewport = new_viewport;
 
-        // Also rewrap scrollback
-        let old_lines_above = std::mem::take(&mut self.lines_above);
-        for row in old_lines_above {
-            if new_cols < self.cols {
-                let split_rows = row.split_to_rows_of_length(new_cols);
-                for split_row in split_rows {
-                    self.lines_above.push_back(split_row);
+        // Split each logical line at the new width
+        let mut reflowed: Vec<Row> = Vec::new();
+        let mut new_cursor = (0, 0);
+        for (line_index, mut cells) in logical.into_iter().enumerate() {
+            // Trailing blanks are dropped, but never the cell under the cursor
+            let has_cursor = line_index == cursor.0;
+            let keep = if has_cursor { cursor.1 + 1 } else { 0 };
+            while cells.len() > keep && cells.last() == Some(&TerminalCharacter::default()) {
+                cells.pop();
+            }
+
+            let mut row = Row::with_capacity(new_cols);
+            let mut iter = cells.into_iter().enumerate().peekable();
+            while let Some((index, cell)) = iter.next() {
+                // A wide character moves to the next row as a whole, with its spacer
+                let paired =
+                    cell.is_wide() && iter.peek().is_some_and(|(_, next)| next.wide_spacer);
+                let width = if paired && new_cols > 1 { 2 } else { 1 };
+                if row.len() + width > new_cols {
+                    row.fill_to_width(new_cols);
+                    reflowed.push(std::mem::replace(&mut row, Row::with_capacity(new_cols)));
+                    row.is_canonical = false;
+                }
+                if has_cursor && (index == cursor.1 || paired && index + 1 == cursor.1) {
+                    new_cursor = (reflowed.len(), row.len());
+                }
+                let spacer = paired.then(|| iter.next().expect("peeked").1);
+                match spacer {
+                    Some(spacer) if width == 2 => {
+                        row.columns.push_back(cell);
+                        row.columns.push_back(spacer);
+                    }
+                    // Wide characters without room or without their spacer, and orphaned
+                    // spacers, become blanks
+                    _ if cell.is_wide() || cell.wide_spacer => {
+                        row.columns.push_back(TerminalCharacter::default());
+                    }
+                    _ => row.columns.push_back(cell),
                 }
-            } else {
-                let mut new_row = row;
-                new_row.fill_to_width(new_cols);
-                new_row.truncate(new_cols);
-                self.lines_above.push_back(new_row);
             }
+            row.fill_to_width(new_cols);
+            reflowed.push(row);
+        }
+
+        // Keep the cursor on screen: drop blank rows below it before anything scrolls off
+        let blank = |row: &Row| {
+            row.columns
+                .iter()
+                .all(|c| *c == TerminalCharacter::default())
+        };
+        while reflowed.len() > new_rows
+            && reflowed.len() > new_cursor.0 + 1
+            && reflowed.last().is_some_and(blank)
+        {
+            reflowed.pop();
         }
+        while reflowed.len() < new_rows {
+            reflowed.push(Row::filled(new_cols));
+        }
+        let top = (reflowed.len() - new_rows).min(new_cursor.0);
+        self.lines_below = reflowed.split_off(top + new_rows);
+        self.viewport = reflowed.split_off(top);
+        self.lines_above = reflowed.into();
+        self.trim_scrollback();
+
+        self.rows = new_rows;
+        self.cols = new_cols;
+        self.cursor_row = new_cursor.0 - top;
+        self.cursor_col = new_cursor.1.min(new_cols - 1);
+        self.scroll_region = (0, new_rows - 1);
+        self.left_margin = 0;
+        self.right_margin = new_cols - 1;
+        self.mark_all_changed();
     }
 
     /// Fix wide characters that are split at the edge after resize.
@@ -617,15 +906,76 @@ mod tests {
     #[test]
     fn test_grid_changed_lines() {
         let mut grid = Grid::new(24, 80);
-        grid.clear_changed();
+        grid.clear_dirty();
 
         grid.put_char('X');
-        assert!(grid.changed_lines.contains(&0));
+        assert!(grid.is_row_dirty(0));
 
         grid.cursor_row = 5;
         grid.cursor_col = 0;
         grid.put_char('Y');
-        assert!(grid.changed_lines.contains(&5));
+        assert!(grid.is_row_dirty(5));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_grid_changed_lines_wrappers_follow_dirty_flags() {
+        let mut grid = Grid::new(4, 10);
+        grid.clear_changed();
+        assert!(grid.get_changed_lines().is_empty());
+
+        grid.set_char(2, 0, TerminalCharacter::new('Z', SharedStyles::Default));
+        assert_eq!(grid.get_changed_lines(), HashSet::from([2]));
+        assert!(grid.is_row_dirty(2));
+    }
+
+    #[test]
+    fn test_grid_dirty_rows_track_single_cell_writes() {
+        let mut grid = Grid::new(24, 80);
+        assert_eq!(grid.dirty_rows().len(), 24);
+        grid.clear_dirty();
+        assert!(grid.dirty_rows().is_empty());
+
+        grid.set_char(7, 3, TerminalCharacter::new('Z', SharedStyles::Default));
+        assert_eq!(grid.dirty_rows(), vec![7]);
+
+        grid.clear_dirty();
+        grid.cursor_row = 2;
+        grid.clear_line();
+        assert_eq!(grid.dirty_rows(), vec![2]);
+    }
+
+    #[test]
+    fn test_grid_scrolling_marks_region_dirty() {
+        let mut grid = Grid::new(10, 20);
+        grid.scroll_region = (2, 5);
+        grid.clear_dirty();
+
+        grid.scroll_up_in_region(1);
+        assert_eq!(grid.dirty_rows(), vec![2, 3, 4, 5]);
+
+        grid.clear_dirty();
+        grid.scroll_down_in_region(2);
+        assert_eq!(grid.dirty_rows(), vec![2, 3, 4, 5]);
+
+        grid.resize(12, 20);
+        assert_eq!(grid.dirty_rows(), (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_grid_scrollback_cap() {
+        let mut grid = Grid::with_scrollback(2, 10, 5);
+        for _ in 0..20 {
+            grid.put_char('x');
+            grid.newline();
+        }
+        assert_eq!(grid.lines_above.len(), 5);
+
+        let mut none = Grid::with_scrollback(2, 10, 0);
+        for _ in 0..5 {
+            none.newline();
+        }
+        assert!(none.lines_above.is_empty());
     }
 
     #[test]
@@ -641,4 +991,63 @@ mod tests {
         // Original content should be preserved
         assert_eq!(grid.viewport[0].columns[0].character, 'A');
     }
+
+    /// Print `text` with auto-wrap the way the terminal does: a full row continues on
+    /// the next one, which is marked as a soft wrap.
+    fn type_wrapped(grid: &mut Grid, text: &str) {
+        for c in text.chars() {
+            if grid.cursor_col >= grid.cols {
+                grid.cursor_col = 0;
+                grid.newline();
+                grid.viewport[grid.cursor_row].is_canonical = false;
+            }
+            grid.put_char(c);
+        }
+    }
+
+    fn row_text(row: &Row) -> String {
+        let text: String = row.columns.iter().map(|c| c.character).collect();
+        text.trim_end().to_string()
+    }
+
+    #[test]
+    fn test_grid_resize_reflow_round_trip() {
+        let line: String = (0..120)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let mut grid = Grid::new(5, 80);
+        type_wrapped(&mut grid, &line);
+        grid.cursor_col = 0;
+        grid.newline();
+        type_wrapped(&mut grid, "next");
+
+        // Plain resize cuts the line
+        let mut cut = grid.clone();
+        cut.resize(5, 40);
+        assert_eq!(row_text(&cut.viewport[0]), line[..40]);
+        assert_eq!(row_text(&cut.viewport[1]), line[80..]);
+
+        grid.resize_reflow(5, 40);
+        let rows: Vec<String> = grid.viewport.iter().map(row_text).collect();
+        assert_eq!(rows, [&line[..40], &line[40..80], &line[80..], "next", ""]);
+        assert!(!grid.viewport[2].is_canonical && grid.viewport[3].is_canonical);
+        assert_eq!((grid.cursor_row, grid.cursor_col), (3, 4));
+
+        grid.resize_reflow(5, 80);
+        let rows: Vec<String> = grid.viewport.iter().map(row_text).collect();
+        assert_eq!(rows, [&line[..80], &line[80..], "next", "", ""]);
+        assert_eq!((grid.cursor_row, grid.cursor_col), (2, 4));
+        assert_eq!(grid.scrollback_len(), 0);
+    }
+
+    #[test]
+    fn test_grid_resize_reflow_moves_wide_chars_whole() {
+        let mut grid = Grid::new(3, 6);
+        type_wrapped(&mut grid, "ab中cd");
+        grid.resize_reflow(3, 3);
+
+        let rows: Vec<String> = grid.viewport.iter().map(row_text).collect();
+        assert_eq!(rows, ["ab", "中 c", "d"]);
+        assert!(grid.viewport[1].columns[1].wide_spacer);
+    }
 }
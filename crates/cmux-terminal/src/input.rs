@@ -0,0 +1,183 @@
+//! Key input encoding.
+//!
+//! Turns special keys into the bytes an xterm-compatible terminal sends to the PTY,
+//! honoring application cursor keys (DECCKM) and application keypad (DECKPAM) modes.
+
+/// A special (non-text) key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// Function key F1-F12; other numbers encode to nothing
+    F(u8),
+    /// Numeric keypad key: `0`-`9`, `.`, `+`, `-`, `*`, `/` or `=`
+    Keypad(char),
+    /// Keypad Enter
+    KeypadEnter,
+}
+
+/// Modifiers held with a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyMods {
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+impl KeyMods {
+    /// xterm modifier parameter (`1 + shift + 2*alt + 4*ctrl`), or None without modifiers.
+    fn param(self) -> Option<u8> {
+        let bits = self.shift as u8 | (self.alt as u8) << 1 | (self.ctrl as u8) << 2;
+        (bits != 0).then_some(1 + bits)
+    }
+}
+
+/// Terminal modes that change what keys send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyModes {
+    /// DECCKM: cursor keys send SS3 (`ESC O`) instead of CSI
+    pub application_cursor: bool,
+    /// DECKPAM: keypad keys send SS3 sequences instead of their characters
+    pub application_keypad: bool,
+}
+
+/// Bytes to send for `key` with `mods` held, given the terminal's current `modes`.
+/// Modified cursor and function keys use xterm's `CSI 1 ; m X` / `CSI n ; m ~` forms.
+pub fn encode_key(key: Key, mods: KeyMods, modes: KeyModes) -> Vec<u8> {
+    let modifier = mods.param();
+    // Keys that end in a final letter: CSI/SS3 X, or CSI 1 ; m X when modified
+    let letter = |final_byte: char, ss3: bool| match modifier {
+        Some(m) => format!("\x1b[1;{}{}", m, final_byte).into_bytes(),
+        None if ss3 => format!("\x1bO{}", final_byte).into_bytes(),
+        None => format!("\x1b[{}", final_byte).into_bytes(),
+    };
+    // Keys that end in a tilde: CSI n ~, or CSI n ; m ~ when modified
+    let tilde = |code: u8| match modifier {
+        Some(m) => format!("\x1b[{};{}~", code, m).into_bytes(),
+        None => format!("\x1b[{}~", code).into_bytes(),
+    };
+
+    match key {
+        Key::Up => letter('A', modes.application_cursor),
+        Key::Down => letter('B', modes.application_cursor),
+        Key::Right => letter('C', modes.application_cursor),
+        Key::Left => letter('D', modes.application_cursor),
+        Key::Home => letter('H', modes.application_cursor),
+        Key::End => letter('F', modes.application_cursor),
+        Key::Insert => tilde(2),
+        Key::Delete => tilde(3),
+        Key::PageUp => tilde(5),
+        Key::PageDown => tilde(6),
+        Key::F(n @ 1..=4) => letter((b'P' + n - 1) as char, true),
+        Key::F(n @ 5..=12) => tilde([15, 17, 18, 19, 20, 21, 23, 24][(n - 5) as usize]),
+        Key::F(_) => Vec::new(),
+        Key::Keypad(c) if modes.application_keypad => match keypad_ss3_final(c) {
+            Some(final_byte) => vec![0x1b, b'O', final_byte],
+            None => Vec::new(),
+        },
+        Key::Keypad(c) if keypad_ss3_final(c).is_some() => vec![c as u8],
+        Key::Keypad(_) => Vec::new(),
+        Key::KeypadEnter if modes.application_keypad => b"\x1bOM".to_vec(),
+        Key::KeypadEnter => b"\r".to_vec(),
+    }
+}
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Wrap pasted data in bracketed paste guards. Any guard sequence inside the data is
+/// removed first (repeatedly, so removals can't splice a new one together), so the
+/// payload can't end the paste early and have the rest run as typed input.
+pub(crate) fn bracket_paste(data: &[u8]) -> Vec<u8> {
+    let mut payload = data.to_vec();
+    loop {
+        let mut cleaned = Vec::with_capacity(payload.len());
+        let mut i = 0;
+        while i < payload.len() {
+            let rest = &payload[i..];
+            if rest.starts_with(PASTE_START) || rest.starts_with(PASTE_END) {
+                i += PASTE_END.len();
+            } else {
+                cleaned.push(payload[i]);
+                i += 1;
+            }
+        }
+        if cleaned.len() == payload.len() {
+            break;
+        }
+        payload = cleaned;
+    }
+
+    let mut wrapped = Vec::with_capacity(payload.len() + PASTE_START.len() + PASTE_END.len());
+    wrapped.extend_from_slice(PASTE_START);
+    wrapped.extend_from_slice(&payload);
+    wrapped.extend_from_slice(PASTE_END);
+    wrapped
+}
+
+/// Final byte of the SS3 sequence a keypad key sends in application keypad mode.
+fn keypad_ss3_final(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' => Some(b'p' + (c as u8 - b'0')),
+        '*' => Some(b'j'),
+        '+' => Some(b'k'),
+        '-' => Some(b'm'),
+        '.' => Some(b'n'),
+        '/' => Some(b'o'),
+        '=' => Some(b'X'),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_keys_for_each_mode() {
+        let normal = KeyModes::default();
+        let app = KeyModes {
+            application_cursor: true,
+            application_keypad: true,
+        };
+        let none = KeyMods::default();
+        let ctrl = KeyMods {
+            ctrl: true,
+            ..KeyMods::default()
+        };
+
+        assert_eq!(encode_key(Key::Up, none, normal), b"\x1b[A");
+        assert_eq!(encode_key(Key::Up, none, app), b"\x1bOA");
+        assert_eq!(encode_key(Key::Up, ctrl, app), b"\x1b[1;5A");
+        assert_eq!(encode_key(Key::End, none, app), b"\x1bOF");
+        assert_eq!(encode_key(Key::Delete, ctrl, normal), b"\x1b[3;5~");
+        assert_eq!(encode_key(Key::F(1), none, normal), b"\x1bOP");
+        assert_eq!(encode_key(Key::F(12), none, normal), b"\x1b[24~");
+        assert_eq!(encode_key(Key::F(13), none, normal), b"");
+        assert_eq!(encode_key(Key::Keypad('5'), none, normal), b"5");
+        assert_eq!(encode_key(Key::Keypad('5'), none, app), b"\x1bOu");
+        assert_eq!(encode_key(Key::KeypadEnter, none, app), b"\x1bOM");
+    }
+
+    #[test]
+    fn bracket_paste_strips_embedded_guards() {
+        assert_eq!(bracket_paste(b"ls"), b"\x1b[200~ls\x1b[201~");
+        assert_eq!(
+            bracket_paste(b"a\x1b[201~rm -rf ~\x1b[200~b"),
+            b"\x1b[200~arm -rf ~b\x1b[201~"
+        );
+        // Removing the inner terminator must not leave a new one behind
+        assert_eq!(
+            bracket_paste(b"\x1b[20\x1b[201~1~x"),
+            b"\x1b[200~x\x1b[201~"
+        );
+    }
+}
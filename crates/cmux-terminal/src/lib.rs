@@ -22,12 +22,21 @@
 mod character;
 mod filter;
 mod grid;
+mod input;
+mod sixel;
 mod terminal;
 
 pub use character::{CharacterStyles, ColorPalette, Row, SharedStyles, TerminalCharacter};
-pub use filter::{filter_da_queries, DaFilter};
-pub use grid::Grid;
-pub use terminal::{Cell, VirtualTerminal};
+pub use filter::{filter_da_queries, DaFilter, FilterStats};
+pub use grid::{Grid, DEFAULT_MAX_SCROLLBACK};
+pub use input::{encode_key, Key, KeyModes, KeyMods};
+pub use sixel::{SixelImage, MAX_SIXEL_DIMENSION, MAX_SIXEL_PIXEL_WRITES};
+pub use terminal::{
+    Cell, CellChange, CharsetState, ChecksumMode, CursorStyle, EventHandler, FrameSnapshot,
+    ResizeOutcome, TerminalEvent, TerminalSnapshot, UnknownSequence, VirtualTerminal,
+    DEFAULT_MAX_GRID_DIMENSION, MAX_CURSOR_STACK_DEPTH, MAX_SIXEL_IMAGES, MAX_UNKNOWN_SEQUENCES,
+    SNAPSHOT_VERSION,
+};
 
 // Re-export ratatui types that are used in the public API
 pub use ratatui::style::{Color, Modifier, Style};
@@ -0,0 +1,299 @@
+//! Sixel graphics decoding.
+//!
+//! Decodes the data of a `DCS P1 ; P2 ; P3 q ... ST` sequence into an RGBA bitmap.
+//! Supported commands: color introducer (`#`), raster attributes (`"`), repeat (`!`),
+//! graphics carriage return (`$`) and graphics new line (`-`).
+
+/// Images are clipped to this many pixels in each direction.
+pub const MAX_SIXEL_DIMENSION: usize = 2048;
+
+/// Pixel writes allowed while decoding one image. Repeats and graphics carriage returns
+/// can paint the same pixels over and over, so this bounds the work a small sequence
+/// can cause; past it the image is dropped.
+pub const MAX_SIXEL_PIXEL_WRITES: usize = MAX_SIXEL_DIMENSION * MAX_SIXEL_DIMENSION;
+
+/// A decoded Sixel image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SixelImage {
+    /// Viewport row of the cursor when the image was received
+    pub row: usize,
+    /// Viewport column of the cursor when the image was received
+    pub col: usize,
+    /// Width in pixels
+    pub width: usize,
+    /// Height in pixels
+    pub height: usize,
+    /// Row-major RGBA pixels, `width * height * 4` bytes
+    pub pixels: Vec<u8>,
+}
+
+/// Default VT340 color registers 0-15, as RGB percentages.
+const VT340_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (20, 20, 80),
+    (80, 13, 13),
+    (20, 80, 20),
+    (80, 20, 80),
+    (20, 80, 80),
+    (80, 80, 20),
+    (53, 53, 53),
+    (26, 26, 26),
+    (33, 33, 60),
+    (60, 26, 26),
+    (33, 60, 33),
+    (60, 33, 60),
+    (33, 60, 60),
+    (60, 60, 33),
+    (80, 80, 80),
+];
+
+/// Decode sixel data (the bytes between `q` and ST). With `transparent_background`
+/// (P2 = 1) pixels no sixel sets stay fully transparent, otherwise they get color
+/// register 0. Returns `None` for an empty image or one over [`MAX_SIXEL_PIXEL_WRITES`].
+pub(crate) fn decode(data: &[u8], transparent_background: bool) -> Option<SixelImage> {
+    let mut palette = [[0u8, 0, 0, 255]; 256];
+    for (slot, &(r, g, b)) in palette.iter_mut().zip(VT340_PALETTE.iter()) {
+        *slot = [percent(r), percent(g), percent(b), 255];
+    }
+    // Pixels no sixel has set yet are None until the background is known
+    let mut rows: Vec<Vec<Option<[u8; 4]>>> = Vec::new();
+    let mut declared = (0, 0);
+    let mut color = 0usize;
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut budget = MAX_SIXEL_PIXEL_WRITES;
+    let mut i = 0;
+    while i < data.len() {
+        let byte = data[i];
+        i += 1;
+        match byte {
+            b'"' => {
+                let (params, next) = parse_params(data, i);
+                i = next;
+                // Pan ; Pad ; Ph ; Pv - only the size matters here
+                declared = (
+                    params.get(2).copied().unwrap_or(0).min(MAX_SIXEL_DIMENSION),
+                    params.get(3).copied().unwrap_or(0).min(MAX_SIXEL_DIMENSION),
+                );
+            }
+            b'#' => {
+                let (params, next) = parse_params(data, i);
+                i = next;
+                let Some(&register) = params.first() else {
+                    continue;
+                };
+                color = register % palette.len();
+                if let [_, space, a, b, c, ..] = params[..] {
+                    match space {
+                        1 => palette[color] = hls_to_rgba(a, b, c),
+                        2 => palette[color] = [percent_of(a), percent_of(b), percent_of(c), 255],
+                        _ => {}
+                    }
+                }
+            }
+            b'!' => {
+                let (params, next) = parse_params(data, i);
+                i = next;
+                let count = params.first().copied().unwrap_or(1).max(1);
+                if let Some(&sixel @ b'?'..=b'~') = data.get(i) {
+                    i += 1;
+                    let written = draw(&mut rows, x, y, count, sixel - b'?', palette[color]);
+                    budget = budget.checked_sub(written)?;
+                    x = x.saturating_add(count);
+                }
+            }
+            b'$' => x = 0,
+            b'-' => {
+                x = 0;
+                y = y.saturating_add(6);
+            }
+            b'?'..=b'~' => {
+                let written = draw(&mut rows, x, y, 1, byte - b'?', palette[color]);
+                budget = budget.checked_sub(written)?;
+                x = x.saturating_add(1);
+            }
+            _ => {}
+        }
+    }
+
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0).max(declared.0);
+    let height = rows.len().max(declared.1);
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let background = if transparent_background {
+        [0, 0, 0, 0]
+    } else {
+        palette[0]
+    };
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for row_idx in 0..height {
+        let row = rows.get(row_idx).map(Vec::as_slice).unwrap_or(&[]);
+        for col in 0..width {
+            let color = row.get(col).copied().flatten().unwrap_or(background);
+            pixels.extend_from_slice(&color);
+        }
+    }
+    Some(SixelImage {
+        row: 0,
+        col: 0,
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Paint one sixel (six vertical pixels, bit 0 on top) `count` times starting at `x, y`.
+/// Returns the number of pixels written.
+fn draw(
+    rows: &mut Vec<Vec<Option<[u8; 4]>>>,
+    x: usize,
+    y: usize,
+    count: usize,
+    bits: u8,
+    color: [u8; 4],
+) -> usize {
+    let end = x.saturating_add(count).min(MAX_SIXEL_DIMENSION);
+    if x >= end {
+        return 0;
+    }
+    let mut written = 0;
+    for bit in 0..6 {
+        let py = y + bit;
+        if py >= MAX_SIXEL_DIMENSION {
+            break;
+        }
+        if rows.len() <= py {
+            rows.resize_with(py + 1, Vec::new);
+        }
+        if bits & (1 << bit) == 0 {
+            continue;
+        }
+        let row = &mut rows[py];
+        if row.len() < end {
+            row.resize(end, None);
+        }
+        row[x..end].fill(Some(color));
+        written += end - x;
+    }
+    written
+}
+
+/// Parse `;`-separated decimal parameters starting at `start`, returning them and the
+/// index of the first byte after them.
+fn parse_params(data: &[u8], start: usize) -> (Vec<usize>, usize) {
+    let mut params = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut i = start;
+    while let Some(&byte) = data.get(i) {
+        match byte {
+            b'0'..=b'9' => {
+                let digit = (byte - b'0') as usize;
+                current = Some(
+                    current
+                        .unwrap_or(0)
+                        .saturating_mul(10)
+                        .saturating_add(digit),
+                );
+            }
+            b';' => params.push(current.take().unwrap_or(0)),
+            _ => break,
+        }
+        i += 1;
+    }
+    if let Some(value) = current {
+        params.push(value);
+    }
+    (params, i)
+}
+
+fn percent(value: u8) -> u8 {
+    percent_of(value as usize)
+}
+
+/// Scale a 0-100 percentage to 0-255.
+fn percent_of(value: usize) -> u8 {
+    ((value.min(100) * 255 + 50) / 100) as u8
+}
+
+/// Convert a DEC HLS color (hue 0-360 with blue at 0, lightness and saturation 0-100).
+fn hls_to_rgba(hue: usize, lightness: usize, saturation: usize) -> [u8; 4] {
+    // DEC puts blue at 0 degrees and red at 120; shift to the usual red-at-0 wheel
+    let h = ((hue % 360 + 240) % 360) as f32 / 360.0;
+    let l = lightness.min(100) as f32 / 100.0;
+    let s = saturation.min(100) as f32 / 100.0;
+    if s == 0.0 {
+        let v = (l * 255.0).round() as u8;
+        return [v, v, v, 255];
+    }
+    let q = if l < 0.5 {
+        l * (1.0 + s)
+    } else {
+        l + s - l * s
+    };
+    let p = 2.0 * l - q;
+    let channel = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (v * 255.0).round() as u8
+    };
+    [
+        channel(h + 1.0 / 3.0),
+        channel(h),
+        channel(h - 1.0 / 3.0),
+        255,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel(image: &SixelImage, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * image.width + x) * 4;
+        image.pixels[i..i + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn decodes_colors_repeats_and_raster_size() {
+        // 4x12 declared; red (RGB) 3 columns of full sixels, then a green (HLS) pixel on the
+        // top of the second band
+        let image = decode(b"\"1;1;4;12#1;2;100;0;0!3~-#2;1;240;50;100@", true).unwrap();
+        assert_eq!((image.width, image.height), (4, 12));
+        assert_eq!(image.pixels.len(), 4 * 12 * 4);
+        for x in 0..3 {
+            for y in 0..6 {
+                assert_eq!(pixel(&image, x, y), [255, 0, 0, 255]);
+            }
+        }
+        assert_eq!(pixel(&image, 3, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&image, 0, 6), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 0, 7), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn opaque_background_uses_register_zero() {
+        let image = decode(b"#0;2;0;0;100#1;2;100;100;100A", false).unwrap();
+        assert_eq!((image.width, image.height), (1, 6));
+        // 'A' sets bit 1 only
+        assert_eq!(pixel(&image, 0, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 0, 1), [255, 255, 255, 255]);
+        assert!(decode(b"", false).is_none());
+    }
+
+    #[test]
+    fn repainting_past_the_write_budget_drops_the_image() {
+        // Each `$!2048~` repaints the same 2048x6 band
+        let band = b"$!2048~";
+        let fits = MAX_SIXEL_PIXEL_WRITES / (2048 * 6);
+        assert!(decode(&band.repeat(fits), true).is_some());
+        assert!(decode(&band.repeat(fits + 1), true).is_none());
+    }
+}
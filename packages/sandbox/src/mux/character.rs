@@ -296,6 +296,33 @@ impl Row {
         self.columns.iter().map(|c| c.width()).sum()
     }
 
+    /// Map a display column to the index of the cell drawn there.
+    /// Both columns of a wide character map to the wide character's cell, never its spacer.
+    pub fn cell_at_column(&self, display_col: usize) -> Option<usize> {
+        let mut col = 0;
+        for (index, character) in self.columns.iter().enumerate() {
+            let width = character.width();
+            if display_col < col + width {
+                return Some(index);
+            }
+            col += width;
+        }
+        None
+    }
+
+    /// Map a cell index to the display column where it starts (the inverse of
+    /// [`cell_at_column`](Self::cell_at_column)). A wide-char spacer reports the
+    /// second column of its wide character.
+    pub fn column_of_cell(&self, index: usize) -> Option<usize> {
+        let character = self.columns.get(index)?;
+        let col: usize = self.columns.iter().take(index).map(|c| c.width()).sum();
+        if character.wide_spacer {
+            Some(col.saturating_sub(1))
+        } else {
+            Some(col)
+        }
+    }
+
     /// Get a character at the given column index.
     #[inline]
     pub fn get(&self, index: usize) -> Option<&TerminalCharacter> {
@@ -634,6 +661,38 @@ mod tests {
         assert!(row.columns[2].wide_spacer);
     }
 
+    #[test]
+    fn test_row_column_cell_mapping_with_wide_chars() {
+        let mut row = Row::new();
+        let mut x = 0;
+        for c in "a中b文".chars() {
+            x = row.add_character_at(TerminalCharacter::new(c, SharedStyles::Default), x);
+        }
+        // Cells: a, 中, spacer, b, 文, spacer
+        assert_eq!(row.cell_at_column(0), Some(0));
+        assert_eq!(row.cell_at_column(1), Some(1));
+        assert_eq!(row.cell_at_column(2), Some(1));
+        assert_eq!(row.cell_at_column(3), Some(3));
+        assert_eq!(row.cell_at_column(4), Some(4));
+        assert_eq!(row.cell_at_column(5), Some(4));
+        assert_eq!(row.cell_at_column(6), None);
+
+        assert_eq!(row.column_of_cell(0), Some(0));
+        assert_eq!(row.column_of_cell(1), Some(1));
+        assert_eq!(row.column_of_cell(2), Some(2));
+        assert_eq!(row.column_of_cell(3), Some(3));
+        assert_eq!(row.column_of_cell(4), Some(4));
+        assert_eq!(row.column_of_cell(6), None);
+        for col in 0..6 {
+            let cell = row.cell_at_column(col).unwrap();
+            assert!(!row.columns[cell].wide_spacer);
+            assert_eq!(
+                row.cell_at_column(row.column_of_cell(cell).unwrap()),
+                Some(cell)
+            );
+        }
+    }
+
     #[test]
     fn test_row_split() {
         let mut row = Row::with_capacity(10);
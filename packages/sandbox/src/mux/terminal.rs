@@ -3197,14 +3197,14 @@ impl TerminalBuffer {
         }
 
         let line = &self.terminal.internal_grid.viewport[row];
-        if col >= line.len() {
-            return None;
-        }
+        // `as_string` yields one char per cell, so the cell index is also the char index.
+        // Mapping through the cell keeps a click on a wide char's second column on that char.
+        let cell = line.cell_at_column(col)?;
 
         let line_text = line.as_string();
         let line_text = line_text.trim_end();
 
-        if let Some(url) = find_url_at_column(line_text, col) {
+        if let Some(url) = find_url_at_column(line_text, cell) {
             // Check for multi-line URL continuation
             let cols = self.terminal.cols();
             if line_text.len() >= cols.saturating_sub(1) && !url.is_empty() {